@@ -1,10 +1,21 @@
 #![allow(unused_imports)]
 
 use conrod_core::{text::rt::Rect, Scalar};
-use conrod_example_shared as example;
 use conrod_piston::{draw::primitives as draw_primitives, event::convert};
-use example::DemoApp;
-use pagepal::{fullscreen, library::Library, theme, Manga, Novel, APPNAME};
+use pagepal::{
+    config_dir,
+    data_dir,
+    fullscreen,
+    library::Library,
+    reader::{Action, Reader, Typography},
+    theme,
+    ui::{Adder, AdderIds, Browser, BrowserIds, Choice, Downloads},
+    Label,
+    Manga,
+    Novel,
+    Queue,
+    APPNAME,
+};
 use piston_window::{
     texture::{Format::Rgba8, UpdateTexture},
     AdvancedWindow,
@@ -14,6 +25,7 @@ use piston_window::{
     G2d,
     G2dTexture,
     Key,
+    MouseScrollEvent,
     OpenGL,
     PistonWindow,
     PressEvent,
@@ -32,16 +44,58 @@ use std::path::PathBuf;
 #[tokio::main]
 pub async fn main() {
     let gl = OpenGL::V4_5;
-    const WIDTH: u32 = example::WIN_W;
-    const HEIGHT: u32 = example::WIN_H;
+    const WIDTH: u32 = 1100;
+    const HEIGHT: u32 = 800;
     let assets = PathBuf::from("assets");
     let font_path = assets.join("NotoSans-Regular.ttf");
-    let _library: Library<Novel, Manga> = Library::default();
+    let mut library: Library<Novel, Manga> = match Library::open(&data_dir()) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!(
+                "Couldn't load the library from {}: {}",
+                data_dir().display(),
+                e
+            );
+            eprintln!("Fix or move away its library.json to start anew.");
+            std::process::exit(1)
+        }
+    };
+    match library.load_sites(&config_dir().join("sites")) {
+        Ok((_, failed)) => failed.iter().for_each(|(path, e)| {
+            eprintln!("Skipped the site definition {}: {}", path.display(), e)
+        }),
+        Err(e) => eprintln!("Couldn't load site definitions: {}", e),
+    }
+    let queue_path = data_dir().join("queue.json");
+    let mut queue = match Queue::open(&queue_path) {
+        Ok(q) => q,
+        Err(e) => {
+            let bad = queue_path.with_extension("json.bad");
+            eprintln!(
+                "Couldn't read the download queue, moved it to {}: {}",
+                bad.display(),
+                e
+            );
+            let moved = std::fs::rename(&queue_path, &bad).map_err(Into::into);
+            match moved.and_then(|_| Queue::open(&queue_path)) {
+                Ok(q) => q,
+                Err(e) => {
+                    eprintln!("Couldn't start a new download queue: {}", e);
+                    std::process::exit(1)
+                }
+            }
+        }
+    };
+    let downloads = Downloads::new(tokio::runtime::Handle::current());
+    for label in queue.books() {
+        downloads.start(&library, queue.pending(&label));
+    }
+    downloads.fetch_covers(&library);
 
     let mut window: PistonWindow<Sdl2Window> =
         WindowSettings::new(APPNAME, [WIDTH, HEIGHT])
             .samples(16)
-            .exit_on_esc(true)
+            .exit_on_esc(false)
             .vsync(true)
             .resizable(true)
             .graphics_api(gl)
@@ -54,7 +108,7 @@ pub async fn main() {
     let mut ui = conrod_core::UiBuilder::new([WIDTH as f64, HEIGHT as f64])
         .theme(theme())
         .build();
-    ui.fonts.insert_from_file(font_path).unwrap();
+    let font = ui.fonts.insert_from_file(font_path).unwrap();
 
     let mut ctx = window.create_texture_context();
 
@@ -78,23 +132,18 @@ pub async fn main() {
         (cache, texture)
     };
 
-    // Instantiate the generated list of widget identifiers.
-    let ids = example::Ids::new(ui.widget_id_generator());
-
-    // Load the rust logo from file to a piston_window texture.
-    let rust_logo: G2dTexture = {
-        let path = assets.join("images/rust.png");
-        let settings = TextureSettings::new();
-        Texture::from_path(&mut ctx, &path, Flip::None, &settings).unwrap()
-    };
-
     // Create our `conrod_core::image::Map` which describes each of our
     // widget->image mappings.
     let mut image_map = conrod_core::image::Map::new();
-    let rust_logo = image_map.insert(rust_logo);
 
-    // A demonstration of some state that we'd like to control with the App.
-    let mut app = DemoApp::new(rust_logo);
+    // Start in the browser, or reading the book named on the command line
+    let style_path = config_dir().join("typography.toml");
+    let mut style = Typography::load(&style_path);
+    let mut browser = Browser::new(BrowserIds::new(ui.widget_id_generator()));
+    let mut open = std::env::args().nth(1).map(Label);
+    let mut reader: Option<Reader> = None;
+    let mut adder: Option<Adder> = None;
+    let mut add = false;
 
     while let Some(e) = window.next() {
         // Convert the src event to a conrod event.
@@ -104,9 +153,54 @@ pub async fn main() {
             ui.handle_event(e);
         }
 
+        match downloads.poll(&mut library, &mut queue) {
+            Ok(done) => {
+                for (job, ok) in done {
+                    if let Some(a) = adder.as_mut() {
+                        a.progress(&job, ok);
+                    }
+                    ui.needs_redraw()
+                }
+            }
+            Err(e) => eprintln!("Couldn't file a download: {}", e),
+        }
+        for res in downloads.covers() {
+            match res {
+                Ok(label) => browser.refresh(&label, &mut image_map),
+                Err(e) => eprintln!("Couldn't save a cover: {}", e),
+            }
+            ui.needs_redraw()
+        }
+        if let Some(label) = open.take() {
+            reader = Reader::open(&library, &label, &mut ui, font, style.clone());
+        }
+        if std::mem::take(&mut add) {
+            adder = Some(Adder::new(AdderIds::new(ui.widget_id_generator())));
+        }
         e.update(|_| {
             let mut ui = ui.set_widgets();
-            example::gui(&mut ui, &ids, &mut app);
+            match (reader.as_mut(), adder.as_mut()) {
+                (Some(r), _) => {
+                    r.set(&mut ui, &mut library, &mut ctx, &mut image_map)
+                }
+                (None, Some(a)) => {
+                    if a.set(&mut ui, &mut library, &downloads, &queue) {
+                        adder = None
+                    }
+                }
+                (None, None) => {
+                    match browser.set(
+                        &mut ui,
+                        &mut library,
+                        &mut ctx,
+                        &mut image_map,
+                    ) {
+                        Some(Choice::Read(label)) => open = Some(label),
+                        Some(Choice::Add) => add = true,
+                        None => {}
+                    }
+                }
+            }
         });
 
         window.draw_2d(&e, |context, graphics, device| {
@@ -152,17 +246,61 @@ pub async fn main() {
         });
         if let Some(button) = e.press_args() {
             if let Button::Keyboard(key) = button {
-                match key {
-                    Key::R => println!("{}", "This"),
-                    Key::Q => break,
-                    Key::F | Key::F12 => {
+                match (key, reader.as_mut()) {
+                    (Key::Escape, None) if adder.is_some() => {
+                        adder = None;
+                        ui.needs_redraw()
+                    }
+                    (Key::Escape, None) => break,
+                    (Key::Escape, Some(r)) => {
+                        if let Some(s) = r.style() {
+                            style = s.clone();
+                        }
+                        r.close(&mut image_map);
+                        reader = None;
+                        ui.needs_redraw()
+                    }
+                    (Key::Q, Some(_)) => break,
+                    (Key::F12, _) | (Key::F, Some(_)) => {
                         fullscreen(&mut window);
                         ui.needs_redraw()
                     }
+                    (key, Some(r)) => {
+                        if let Some(a) = Action::from_key(key) {
+                            if r.act(a, &mut library) {
+                                ui.needs_redraw()
+                            }
+                        }
+                    }
                     _ => {}
                 }
             }
         }
+        if let Some([_, dy]) = e.mouse_scroll_args() {
+            let moved = match reader.as_mut() {
+                Some(r) => r.wheel(dy, &mut library),
+                None => browser.scroll(dy),
+            };
+            if moved {
+                ui.needs_redraw()
+            }
+        }
+    }
+    if let Some(s) = reader.as_ref().and_then(|a| a.style()) {
+        style = s.clone();
+    }
+    if let Err(e) = style.store(&style_path) {
+        eprintln!("Couldn't store the typography settings: {}", e);
+    }
+    if let Err(e) = queue.store() {
+        eprintln!("Couldn't store the download queue: {}", e);
+    }
+    if let Err(e) = library.flush() {
+        eprintln!("Couldn't store the search index: {}", e);
+    }
+    if let Err(e) = library.store(&data_dir()) {
+        eprintln!("Couldn't store the library: {}", e);
+        std::process::exit(1)
     }
 }
 
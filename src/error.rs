@@ -0,0 +1,125 @@
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, Response, StatusCode, Url};
+use std::{
+    fmt::{self, Display},
+    io,
+    time::Duration,
+};
+
+/// Everything that can go wrong while retrieving, parsing or storing a book
+#[derive(Debug)]
+pub enum Error {
+    /// Connection, timeout or body decoding failure
+    Network(reqwest::Error),
+    /// The server answered with a non-success status, and maybe a Retry-After
+    Status(StatusCode, Url, Option<Duration>),
+    /// A malformed or unusable url
+    Url(url::ParseError),
+    /// Content or stored data that couldn't be interpreted
+    Parse(String),
+    /// A selector or lookup that matched nothing
+    Missing(String),
+    Io(io::Error),
+    /// No host or no known way of handling the site
+    UnknownSite(String),
+    /// The site failed too often or is out of quota and is skipped for now
+    Unavailable(String),
+    /// A failed query of the SQLite catalog
+    Catalog(String),
+    /// A book that's in the library already
+    Exists(String),
+}
+
+impl Error {
+    /// Turns a non-success response into `Error::Status`
+    pub fn check(resp: Response) -> Result<Response, Error> {
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+        let after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|a| a.to_str().ok())
+            .and_then(|a| match a.trim().parse::<u64>() {
+                Ok(secs) => Some(Duration::from_secs(secs)),
+                Err(_) => {
+                    (DateTime::parse_from_rfc2822(a).ok()?.with_timezone(&Utc) -
+                        Utc::now())
+                    .to_std()
+                    .ok()
+                }
+            });
+        Err(Error::Status(resp.status(), resp.url().to_owned(), after))
+    }
+
+    /// Timeouts, dropped connections, 429 and 5xx are worth retrying
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Network(e) => e.is_timeout() || e.is_connect(),
+            Error::Status(s, ..) => {
+                *s == StatusCode::TOO_MANY_REQUESTS || s.is_server_error()
+            }
+            _ => false,
+        }
+    }
+
+    /// How long the server asked us to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Status(_, _, after) => *after,
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network(e) => write!(f, "Network error: {}", e),
+            Error::Status(s, u, _) => write!(f, "Got {} from {}", s, u),
+            Error::Url(e) => write!(f, "Bad url: {}", e),
+            Error::Parse(s) => write!(f, "Couldn't parse {}", s),
+            Error::Missing(s) => write!(f, "Couldn't find {}", s),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::UnknownSite(s) => write!(f, "Unknown site {}", s),
+            Error::Unavailable(s) => write!(f, "Skipping {}", s),
+            Error::Catalog(s) => write!(f, "Catalog error: {}", s),
+            Error::Exists(s) => write!(f, "Already have {}", s),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(e) => Some(e),
+            Error::Url(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self { Error::Network(e) }
+}
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self { Error::Url(e) }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self { Error::Io(e) }
+}
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self { Error::Parse(e.to_string()) }
+}
+#[cfg(feature = "catalog")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self { Error::Catalog(e.to_string()) }
+}
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => Error::Io(e),
+            e => Error::Parse(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{Book, Content, Media};
+
+pub mod cbz;
+pub mod epub;
+pub mod pdf;
+
+/// Chapters of the book with their content, or the whole book as a single
+/// chapter if its content isn't filed under chapters
+pub fn sections<T: Media>(book: &Book<T>) -> Vec<(String, Vec<&Content<T>>)> {
+    let chs = book
+        .chs
+        .keys()
+        .map(|&id| (format!("Chapter {}", id + 1), book.chapter(id)))
+        .filter(|a| !a.1.is_empty())
+        .collect::<Vec<_>>();
+    match chs.is_empty() {
+        true => vec![(
+            book.title.0.trim().to_string(),
+            book.content.values().collect(),
+        )],
+        false => chs,
+    }
+}
+
+/// Escapes text for use in xml content and attributes
+pub fn escape(s: &str) -> String {
+    s.chars()
+        .fold(String::with_capacity(s.len()), |mut acc, a| {
+            match a {
+                '&' => acc.push_str("&amp;"),
+                '<' => acc.push_str("&lt;"),
+                '>' => acc.push_str("&gt;"),
+                '"' => acc.push_str("&quot;"),
+                '\'' => acc.push_str("&apos;"),
+                a => acc.push(a),
+            }
+            acc
+        })
+}
@@ -0,0 +1,172 @@
+use crate::{export::escape, Book, Content, Error, Media, Num, Store};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Seek, Write},
+    path::{Path, PathBuf},
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+/// Chapter number as written by `Num::from(f32)`
+fn number(n: &Num) -> String {
+    match n.1 {
+        Some(f) => format!("{}", n.0 as f32 + f as f32 / 200.),
+        None => n.0.to_string(),
+    }
+}
+
+fn comic_info(
+    series: &str, number: Option<&str>, pages: usize, web: &str,
+) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<ComicInfo xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:xsd="http://www.w3.org/2001/XMLSchema">
+  <Series>{}</Series>{}
+  <PageCount>{}</PageCount>
+  <Web>{}</Web>
+  <Manga>Yes</Manga>
+</ComicInfo>
+"#,
+        escape(series),
+        number.map_or(String::new(), |a| format!("\n  <Number>{}</Number>", a)),
+        pages,
+        escape(web)
+    )
+}
+
+/// Packs the pages of chapter `ch`, or of the whole book, into a CBZ with a
+/// `ComicInfo.xml`. Pages are named after their place so they sort in
+/// reading order.
+pub fn write<T: Media, W: Write + Seek>(
+    book: &Book<T>, store: &Store, ch: Option<u16>, out: W,
+) -> Result<W, Error> {
+    let dir = book.dir();
+    let pages = book
+        .content
+        .iter()
+        .filter(|(_, c)| ch.is_none() || c.ch == ch)
+        .collect::<Vec<(&Num, &Content<T>)>>();
+    let count = pages.iter().map(|(_, c)| c.parts + 1).sum();
+    // The site's chapter number, else the one in the urls of the pages, see
+    // `Page::get_place`
+    let num = ch.map(|id| {
+        book.chs
+            .get(&id)
+            .and_then(|a| a.number.clone())
+            .or_else(|| {
+                pages
+                    .first()
+                    .and_then(|(_, c)| c.src.as_ref())
+                    .map(|a| Num(a.get_place().1, None))
+                    .filter(|a| a.0 > 0)
+            })
+            .map_or_else(|| (id + 1).to_string(), |a| number(&a))
+    });
+    let mut zip = ZipWriter::new(out);
+    // Images are compressed already
+    let stored =
+        FileOptions::default().compression_method(CompressionMethod::Stored);
+    for (n, c) in &pages {
+        let ext = c
+            .path(&dir)
+            .extension()
+            .and_then(|a| a.to_str())
+            .unwrap_or("jpg")
+            .to_string();
+        for (i, page) in c.pages(&dir, store)?.iter().enumerate() {
+            zip.start_file(
+                format!(
+                    "{:04}-{:03}-{:02}.{}",
+                    n.0,
+                    n.1.unwrap_or_default(),
+                    i,
+                    ext
+                ),
+                stored,
+            )?;
+            zip.write_all(page)?;
+        }
+    }
+    zip.start_file("ComicInfo.xml", FileOptions::default())?;
+    zip.write_all(
+        comic_info(
+            book.title.0.trim(),
+            num.as_deref(),
+            count,
+            book.index.loc.as_str(),
+        )
+        .as_bytes(),
+    )?;
+    Ok(zip.finish()?)
+}
+
+/// Writes chapter `ch`, or the whole book, as a CBZ to `path`
+pub fn save<T: Media>(
+    book: &Book<T>, store: &Store, ch: Option<u16>, path: &Path,
+) -> Result<(), Error> {
+    Ok(write(book, store, ch, BufWriter::new(File::create(path)?))?.flush()?)
+}
+
+/// Writes every chapter with content to its own CBZ in `dir`
+pub fn save_chapters<T: Media>(
+    book: &Book<T>, store: &Store, dir: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    fs::create_dir_all(dir)?;
+    book.chs
+        .keys()
+        .filter(|&&id| !book.chapter(id).is_empty())
+        .map(|&id| {
+            let path =
+                dir.join(format!("{} - {:04}.cbz", book.title.0.trim(), id + 1));
+            save(book, store, Some(id), &path).map(|_| path)
+        })
+        .collect()
+}
+
+#[test]
+fn cbz_pages() {
+    use crate::{Chapter, Label, Manga, Page};
+    use std::io::{Cursor, Read};
+    let mut book: Book<Manga> = Book {
+        title: Label("Test".to_string()),
+        ..Default::default()
+    };
+    book.chs.insert(0, Chapter {
+        id: 0,
+        src: Some(Page::from("https://example.com/manga/chapter-3.5")),
+        ..Default::default()
+    });
+    for (n, p) in ["1", "2"].iter().enumerate() {
+        let page =
+            Page::from(format!("https://example.com/m/chapter-3/{}.jpg", p));
+        let c = Content::from((page, Content::from(vec![1u8])));
+        book.add_content(0, n, c).unwrap();
+    }
+    let mut zip = zip::ZipArchive::new(
+        write(&book, &Store::default(), Some(0), Cursor::new(vec![])).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(zip.by_index(0).unwrap().name(), "0000-000-00.jpg");
+    assert_eq!(zip.by_index(1).unwrap().name(), "0000-001-00.jpg");
+    let mut info = String::new();
+    zip.by_name("ComicInfo.xml")
+        .unwrap()
+        .read_to_string(&mut info)
+        .unwrap();
+    assert!(info.contains("<Number>3</Number>"));
+    assert!(info.contains("<PageCount>2</PageCount>"));
+
+    // The site's number is kept in the manifest, its Finder isn't
+    book.chs.get_mut(&0).unwrap().number = Some(Num(3, Some(100)));
+    let json = serde_json::to_string(&book.chs[&0]).unwrap();
+    book.chs.insert(0, serde_json::from_str(&json).unwrap());
+    let mut zip = zip::ZipArchive::new(
+        write(&book, &Store::default(), Some(0), Cursor::new(vec![])).unwrap(),
+    )
+    .unwrap();
+    let mut info = String::new();
+    zip.by_name("ComicInfo.xml")
+        .unwrap()
+        .read_to_string(&mut info)
+        .unwrap();
+    assert!(info.contains("<Number>3.5</Number>"));
+}
@@ -0,0 +1,231 @@
+use crate::{
+    export::{escape, sections},
+    Book,
+    Error,
+    Format,
+    Media,
+    Store,
+};
+use chrono::Utc;
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    path::Path,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+static CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn xhtml(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{}</title></head>
+<body>
+{}</body>
+</html>
+"#,
+        title, body
+    )
+}
+
+fn item(id: &str, href: &str, kind: &str, props: Option<&str>) -> String {
+    format!(
+        r#"<item id="{}" href="{}" media-type="{}"{}/>"#,
+        id,
+        href,
+        kind,
+        props.map_or(String::new(), |a| format!(r#" properties="{}""#, a))
+    )
+}
+
+/// Writes `book` as an EPUB 3 package with one document per chapter, and
+/// `cover` as its cover image if there is one. Stored content is read from
+/// `store`.
+pub fn write<T: Media, W: Write + Seek>(
+    book: &Book<T>, store: &Store, cover: Option<&[u8]>, out: W,
+) -> Result<W, Error> {
+    let dir = book.dir();
+    let title = escape(book.title.0.trim());
+    let deflated = FileOptions::default();
+    let mut zip = ZipWriter::new(out);
+    // Has to be the first entry and uncompressed
+    zip.start_file(
+        "mimetype",
+        FileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER.as_bytes())?;
+
+    let mut manifest = vec![item(
+        "nav",
+        "nav.xhtml",
+        "application/xhtml+xml",
+        Some("nav"),
+    )];
+    let mut spine = vec![];
+    let mut toc = vec![];
+    if let Some(c) = cover {
+        let format = Format::sniff(c).unwrap_or_default();
+        let href = format!("cover.{}", format.ext());
+        zip.start_file(format!("OEBPS/{}", href), deflated)?;
+        zip.write_all(c)?;
+        manifest.push(item("cover", &href, format.mime(), Some("cover-image")));
+    }
+    for (n, (name, contents)) in sections(book).into_iter().enumerate() {
+        let id = format!("ch{:04}", n);
+        let name = escape(&name);
+        let mut body = format!("<h2>{}</h2>\n", name);
+        for (i, c) in contents.iter().enumerate() {
+            match T::visual() {
+                true => {
+                    for (j, bytes) in c.pages(&dir, store)?.iter().enumerate() {
+                        let format = c
+                            .format
+                            .or_else(|| Format::sniff(bytes))
+                            .unwrap_or_default();
+                        let id = format!("img{:04}-{:04}-{:02}", n, i, j);
+                        let img = format!("images/{}.{}", id, format.ext());
+                        zip.start_file(format!("OEBPS/{}", img), deflated)?;
+                        zip.write_all(bytes)?;
+                        manifest.push(item(&id, &img, format.mime(), None));
+                        body += &format!(
+                            "<div><img src=\"{}\" alt=\"\"/></div>\n",
+                            img
+                        );
+                    }
+                }
+                false => String::from_utf8_lossy(&c.bytes(&dir, store)?)
+                    .split("\n\n")
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .for_each(|p| body += &format!("<p>{}</p>\n", escape(p))),
+            }
+        }
+        zip.start_file(format!("OEBPS/{}.xhtml", id), deflated)?;
+        zip.write_all(xhtml(&name, &body).as_bytes())?;
+        manifest.push(item(
+            &id,
+            &format!("{}.xhtml", id),
+            "application/xhtml+xml",
+            None,
+        ));
+        toc.push(format!("<li><a href=\"{}.xhtml\">{}</a></li>", id, name));
+        spine.push(format!(r#"<itemref idref="{}"/>"#, id));
+    }
+
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(
+        xhtml(
+            &title,
+            &format!(
+                "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n<ol>\n{}\n</ol>\n</nav>\n",
+                title,
+                toc.join("\n")
+            ),
+        )
+        .as_bytes(),
+    )?;
+    // Sites give e.g. `en_US` as the og:locale, EPUB wants `en-US`
+    let language = book
+        .meta
+        .language
+        .as_deref()
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map_or("en".to_string(), |a| a.replace('_', "-"));
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">{}</dc:identifier>
+    <dc:title>{}</dc:title>
+    <dc:language>{}</dc:language>
+    <meta property="dcterms:modified">{}</meta>{}
+  </metadata>
+  <manifest>
+    {}
+  </manifest>
+  <spine>
+    {}
+  </spine>
+</package>
+"#,
+            escape(book.index.loc.as_str()),
+            title,
+            escape(&language),
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            cover.map_or("", |_| "\n    <meta name=\"cover\" content=\"cover\"/>"),
+            manifest.join("\n    "),
+            spine.join("\n    ")
+        )
+        .as_bytes(),
+    )?;
+    Ok(zip.finish()?)
+}
+
+/// Writes `book` as an EPUB to `path`
+pub fn save<T: Media>(
+    book: &Book<T>, store: &Store, cover: Option<&[u8]>, path: &Path,
+) -> Result<(), Error> {
+    let out = BufWriter::new(File::create(path)?);
+    Ok(write(book, store, cover, out)?.flush()?)
+}
+
+#[test]
+fn epub_package() {
+    use crate::{Chapter, Content, Label, Novel, Page};
+    use std::io::{Cursor, Read};
+    let page = Page::from("https://example.com/novel/chapter-1");
+    let mut book: Book<Novel> = Book {
+        title: Label("A & B".to_string()),
+        ..Default::default()
+    };
+    book.chs.insert(0, Chapter {
+        id: 0,
+        src: Some(page.clone()),
+        ..Default::default()
+    });
+    let c = Content::from((page, Content::from("One <1>\n\nTwo".to_string())));
+    book.add_content(0, 0, c).unwrap();
+    let mut zip = zip::ZipArchive::new(
+        write(&book, &Store::default(), None, Cursor::new(vec![])).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+    let mut ch = String::new();
+    zip.by_name("OEBPS/ch0000.xhtml")
+        .unwrap()
+        .read_to_string(&mut ch)
+        .unwrap();
+    assert!(ch.contains("<p>One &lt;1&gt;</p>") && ch.contains("<p>Two</p>"));
+    let mut opf = String::new();
+    zip.by_name("OEBPS/content.opf")
+        .unwrap()
+        .read_to_string(&mut opf)
+        .unwrap();
+    assert!(opf.contains("<dc:title>A &amp; B</dc:title>"));
+    assert!(opf.contains("<dc:language>en</dc:language>"));
+
+    book.meta.language = Some("pt_BR".to_string());
+    let mut zip = zip::ZipArchive::new(
+        write(&book, &Store::default(), None, Cursor::new(vec![])).unwrap(),
+    )
+    .unwrap();
+    let mut opf = String::new();
+    zip.by_name("OEBPS/content.opf")
+        .unwrap()
+        .read_to_string(&mut opf)
+        .unwrap();
+    assert!(opf.contains("<dc:language>pt-BR</dc:language>"));
+}
@@ -0,0 +1,448 @@
+use crate::{export::sections, Book, Error, Media, Store, Transcode};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// A4 in points
+const WIDTH: f32 = 595.;
+const HEIGHT: f32 = 842.;
+const MARGIN: f32 = 56.;
+const SIZE: f32 = 11.;
+const HEADING: f32 = 16.;
+const LEADING: f32 = 15.;
+/// Characters per line, Helvetica averages about half an em per character
+const CHARS: usize = 85;
+/// Lines per page
+const LINES: usize = 48;
+
+/// Objects of a PDF file, numbered from 1
+#[derive(Default)]
+struct Pdf {
+    objs: Vec<Vec<u8>>,
+}
+impl Pdf {
+    fn reserve(&mut self) -> usize {
+        self.objs.push(vec![]);
+        self.objs.len()
+    }
+
+    fn set(&mut self, id: usize, body: impl Into<Vec<u8>>) {
+        self.objs[id - 1] = body.into();
+    }
+
+    fn add(&mut self, body: impl Into<Vec<u8>>) -> usize {
+        let id = self.reserve();
+        self.set(id, body);
+        id
+    }
+
+    fn stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        let head = format!("<< {} /Length {} >>\nstream\n", dict, data.len());
+        self.add([head.as_bytes(), data, b"\nendstream"].concat())
+    }
+
+    fn page(
+        &mut self, parent: usize, fonts: (usize, usize), ops: &[u8],
+        image: Option<usize>,
+    ) -> usize {
+        let contents = self.stream("", ops);
+        self.add(format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources \
+             << /Font << /F1 {} 0 R /F2 {} 0 R >>{} >> /Contents {} 0 R >>",
+            parent,
+            WIDTH,
+            HEIGHT,
+            fonts.0,
+            fonts.1,
+            image.map_or(String::new(), |a| format!(
+                " /XObject << /Im0 {} 0 R >>",
+                a
+            )),
+            contents
+        ))
+    }
+
+    fn write<W: Write>(
+        self, root: usize, info: usize, mut out: W,
+    ) -> Result<W, Error> {
+        let mut buf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = vec![];
+        for (i, body) in self.objs.iter().enumerate() {
+            offsets.push(buf.len());
+            buf.extend(format!("{} 0 obj\n", i + 1).as_bytes());
+            buf.extend(body);
+            buf.extend(b"\nendobj\n");
+        }
+        let xref = buf.len();
+        buf.extend(
+            format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1)
+                .as_bytes(),
+        );
+        offsets
+            .iter()
+            .for_each(|a| buf.extend(format!("{:010} 00000 n \n", a).as_bytes()));
+        buf.extend(
+            format!(
+                "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+                offsets.len() + 1,
+                root,
+                info,
+                xref
+            )
+            .as_bytes(),
+        );
+        out.write_all(&buf)?;
+        Ok(out)
+    }
+}
+
+/// WinAnsi code of a character, Latin-1 plus the punctuation and letters
+/// at 0x80 to 0x9f
+fn win_ansi(a: char) -> Option<u8> {
+    Some(match a {
+        ' '..='~' | '\u{a0}'..='\u{ff}' => a as u32 as u8,
+        '€' => 0x80,
+        '‚' => 0x82,
+        'ƒ' => 0x83,
+        '„' => 0x84,
+        '…' => 0x85,
+        '†' => 0x86,
+        '‡' => 0x87,
+        'ˆ' => 0x88,
+        '‰' => 0x89,
+        'Š' => 0x8a,
+        '‹' => 0x8b,
+        'Œ' => 0x8c,
+        'Ž' => 0x8e,
+        '‘' => 0x91,
+        '’' => 0x92,
+        '“' => 0x93,
+        '”' => 0x94,
+        '•' => 0x95,
+        '–' => 0x96,
+        '—' => 0x97,
+        '˜' => 0x98,
+        '™' => 0x99,
+        'š' => 0x9a,
+        '›' => 0x9b,
+        'œ' => 0x9c,
+        'ž' => 0x9e,
+        'Ÿ' => 0x9f,
+        _ => return None,
+    })
+}
+
+/// A PDF string literal in WinAnsi, characters it can't hold become `?`
+fn literal(s: &str) -> Vec<u8> {
+    let mut out = vec![b'('];
+    for a in s.chars() {
+        match a {
+            '(' | ')' | '\\' => out.extend(&[b'\\', a as u8]),
+            a => out.push(win_ansi(a).unwrap_or(b'?')),
+        }
+    }
+    out.push(b')');
+    out
+}
+
+/// A PDF text string in UTF-16BE, for the outline and document info which
+/// aren't drawn with a font and so can hold any character
+fn text_string(s: &str) -> Vec<u8> {
+    let hex = s
+        .encode_utf16()
+        .map(|a| format!("{:04X}", a))
+        .collect::<String>();
+    format!("<FEFF{}>", hex).into_bytes()
+}
+
+/// Breaks a paragraph into lines of at most `CHARS` characters
+fn wrap(para: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in para.split_whitespace() {
+        if !line.is_empty() &&
+            line.chars().count() + word.chars().count() >= CHARS
+        {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Width, height and color space of a JPEG, from its frame header
+fn jpeg_info(data: &[u8]) -> Option<(u16, u16, &'static str)> {
+    let be =
+        |i: usize| Some(u16::from_be_bytes([*data.get(i)?, *data.get(i + 1)?]));
+    if data.get(..2)? != [0xff, 0xd8] {
+        return None;
+    }
+    let mut i = 2;
+    loop {
+        if *data.get(i)? != 0xff {
+            return None;
+        }
+        match *data.get(i + 1)? {
+            0xff => i += 1,
+            0x01 | 0xd0..=0xd7 => i += 2,
+            0xc0..=0xcf if ![0xc4, 0xc8, 0xcc].contains(&data[i + 1]) => {
+                let color = match *data.get(i + 9)? {
+                    1 => "DeviceGray",
+                    4 => "DeviceCMYK",
+                    _ => "DeviceRGB",
+                };
+                return Some((be(i + 7)?, be(i + 5)?, color));
+            }
+            _ => i += 2 + be(i + 2)? as usize,
+        }
+    }
+}
+
+/// The image as a JPEG, which a PDF can hold as it is
+fn jpeg(data: Cow<[u8]>) -> Result<Cow<[u8]>, Error> {
+    if jpeg_info(&data).is_some() {
+        return Ok(data);
+    }
+    let (_, pages) = Transcode::default().apply(&data)?;
+    pages
+        .into_iter()
+        .next()
+        .map(Cow::Owned)
+        .ok_or_else(|| Error::Parse("an empty image".into()))
+}
+
+/// Writes `book` as a PDF with a bookmark per chapter. Visual media gets a
+/// page per image scaled to fit, text is reflowed under chapter headings.
+/// Images other than JPEGs are re-encoded as JPEG. Text is set in the
+/// standard Helvetica, which only has the WinAnsi characters, the others
+/// show as `?`; the bookmarks and title keep them.
+pub fn write<T: Media, W: Write>(
+    book: &Book<T>, store: &Store, out: W,
+) -> Result<W, Error> {
+    let dir = book.dir();
+    let mut pdf = Pdf::default();
+    let catalog = pdf.reserve();
+    let parent = pdf.reserve();
+    let outlines = pdf.reserve();
+    let fonts = (
+        pdf.add("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"),
+        pdf.add("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"),
+    );
+    let mut pages = vec![];
+    let mut marks = vec![];
+    for (name, contents) in sections(book) {
+        let first = pages.len();
+        match T::visual() {
+            true => {
+                for c in contents {
+                    for data in c.pages(&dir, store)? {
+                        let data = jpeg(data)?;
+                        let (w, h, color) =
+                            jpeg_info(&data).ok_or_else(|| {
+                                Error::Parse(format!(
+                                    "{:?} as a JPEG",
+                                    c.path(&dir)
+                                ))
+                            })?;
+                        let img = pdf.stream(
+                            &format!(
+                                "/Type /XObject /Subtype /Image /Width {} /Height {} \
+                                 /ColorSpace /{} /BitsPerComponent 8 /Filter /DCTDecode",
+                                w, h, color
+                            ),
+                            &data,
+                        );
+                        let scale = ((WIDTH - 2. * MARGIN) / w as f32)
+                            .min((HEIGHT - 2. * MARGIN) / h as f32);
+                        let (w, h) = (w as f32 * scale, h as f32 * scale);
+                        let ops = format!(
+                            "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im0 Do Q",
+                            w,
+                            h,
+                            (WIDTH - w) / 2.,
+                            (HEIGHT - h) / 2.
+                        );
+                        pages.push(pdf.page(
+                            parent,
+                            fonts,
+                            ops.as_bytes(),
+                            Some(img),
+                        ));
+                    }
+                }
+            }
+            false => {
+                let mut lines =
+                    vec![(true, name.to_owned()), (false, String::new())];
+                for c in contents {
+                    let text = String::from_utf8_lossy(&c.bytes(&dir, store)?)
+                        .into_owned();
+                    for para in text.split("\n\n") {
+                        lines.extend(wrap(para).into_iter().map(|a| (false, a)));
+                        lines.push((false, String::new()));
+                    }
+                }
+                for chunk in lines.chunks(LINES) {
+                    let mut ops = b"BT\n".to_vec();
+                    let mut y = HEIGHT - MARGIN;
+                    for (heading, line) in chunk {
+                        let font = match heading {
+                            true => ("F2", HEADING),
+                            false => ("F1", SIZE),
+                        };
+                        ops.extend(
+                            format!(
+                                "/{} {} Tf 1 0 0 1 {} {} Tm ",
+                                font.0, font.1, MARGIN, y
+                            )
+                            .as_bytes(),
+                        );
+                        ops.extend(literal(line));
+                        ops.extend(b" Tj\n");
+                        y -= LEADING;
+                    }
+                    ops.extend(b"ET");
+                    pages.push(pdf.page(parent, fonts, &ops, None));
+                }
+            }
+        }
+        if let Some(&page) = pages.get(first) {
+            marks.push((name, page));
+        }
+    }
+
+    let kids = pages
+        .iter()
+        .map(|a| format!("{} 0 R", a))
+        .collect::<Vec<_>>();
+    pdf.set(
+        parent,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+    );
+    let items = marks.iter().map(|_| pdf.reserve()).collect::<Vec<_>>();
+    for (i, (name, page)) in marks.iter().enumerate() {
+        let link = |rel: &str, j: Option<&usize>| {
+            j.map_or(String::new(), |a| format!(" /{} {} 0 R", rel, a))
+        };
+        let body = [
+            b"<< /Title ".to_vec(),
+            text_string(name),
+            format!(
+                " /Parent {} 0 R{}{} /Dest [{} 0 R /Fit] >>",
+                outlines,
+                link("Prev", i.checked_sub(1).and_then(|a| items.get(a))),
+                link("Next", items.get(i + 1)),
+                page
+            )
+            .into_bytes(),
+        ]
+        .concat();
+        pdf.set(items[i], body);
+    }
+    pdf.set(outlines, match (items.first(), items.last()) {
+        (Some(first), Some(last)) => format!(
+            "<< /Type /Outlines /First {} 0 R /Last {} 0 R /Count {} >>",
+            first,
+            last,
+            items.len()
+        ),
+        _ => "<< /Type /Outlines /Count 0 >>".to_string(),
+    });
+    pdf.set(
+        catalog,
+        format!(
+            "<< /Type /Catalog /Pages {} 0 R /Outlines {} 0 R /PageMode /UseOutlines >>",
+            parent, outlines
+        ),
+    );
+    let info = pdf.add(
+        [
+            b"<< /Title ".to_vec(),
+            text_string(book.title.0.trim()),
+            b" /Producer (pagepal) >>".to_vec(),
+        ]
+        .concat(),
+    );
+    pdf.write(catalog, info, out)
+}
+
+/// Writes `book` as a PDF to `path`
+pub fn save<T: Media>(
+    book: &Book<T>, store: &Store, path: &Path,
+) -> Result<(), Error> {
+    Ok(write(book, store, BufWriter::new(File::create(path)?))?.flush()?)
+}
+
+#[test]
+fn pdf_text() {
+    use crate::{Chapter, Content, Label, Novel, Page};
+    let page = Page::from("https://example.com/novel/chapter-1");
+    let mut book: Book<Novel> = Book {
+        title: Label("Test".to_string()),
+        ..Default::default()
+    };
+    book.chs.insert(0, Chapter {
+        id: 0,
+        src: Some(page.clone()),
+        ..Default::default()
+    });
+    let text = format!("{}\n\n(Two)", "word ".repeat(40));
+    book.add_content(0, 0, Content::from((page, Content::from(text))))
+        .unwrap();
+    let out = String::from_utf8_lossy(
+        &write(&book, &Store::default(), vec![]).unwrap(),
+    )
+    .into_owned();
+    assert!(out.starts_with("%PDF-1.4") && out.ends_with("%%EOF\n"));
+    assert!(out.contains("(Chapter 1) Tj") && out.contains("(\\(Two\\)) Tj"));
+    assert!(out.contains("/Type /Outlines /First"));
+    assert!(out.contains("<< /Title <FEFF"));
+    assert_eq!(wrap(&"word ".repeat(40)).len(), 3);
+    let jpeg = [
+        0xff, 0xd8, 0xff, 0xe0, 0, 2, 0xff, 0xc0, 0, 8, 8, 0, 20, 0, 10, 3,
+    ];
+    assert_eq!(jpeg_info(&jpeg), Some((10, 20, "DeviceRGB")));
+    assert_eq!(literal("’a…€✓"), b"(\x92a\x85\x80?)".to_vec());
+    assert_eq!(text_string("Aé✓"), b"<FEFF004100E92713>".to_vec());
+}
+
+#[test]
+fn pdf_images() {
+    use crate::{Chapter, Content, Label, Manga, Page};
+    use image::{DynamicImage, ImageOutputFormat};
+    let mut book: Book<Manga> = Book {
+        title: Label("Test".to_string()),
+        ..Default::default()
+    };
+    book.chs.insert(0, Chapter {
+        id: 0,
+        src: Some(Page::from("https://example.com/manga/chapter-1")),
+        ..Default::default()
+    });
+    let mut png = vec![];
+    DynamicImage::new_rgb8(4, 6)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .unwrap();
+    let page = Page::from("https://example.com/manga/chapter-1/1.png");
+    book.add_content(0, 0, Content::from((page, Content::from(png))))
+        .unwrap();
+    let out = String::from_utf8_lossy(
+        &write(&book, &Store::default(), vec![]).unwrap(),
+    )
+    .into_owned();
+    assert!(out.contains("/Width 4 /Height 6"));
+    assert!(out.contains("/DCTDecode"));
+}
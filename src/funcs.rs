@@ -1,8 +1,11 @@
+use crate::APPNAME;
 use chrono::Duration;
 use conrod_core::Theme;
+use directories_next::ProjectDirs;
 use piston_window::PistonWindow;
 use sdl2::video::FullscreenType;
 use sdl2_window::Sdl2Window;
+use std::path::PathBuf;
 
 #[inline]
 pub fn theme() -> Theme {
@@ -48,3 +51,17 @@ pub fn fullscreen(window: &mut PistonWindow<Sdl2Window>) {
 }
 #[inline]
 pub fn duration() -> Duration { Duration::milliseconds(300) }
+/// Platform data directory for the app, `./library` if it can't be determined
+#[inline]
+pub fn data_dir() -> PathBuf {
+    ProjectDirs::from("", "", APPNAME)
+        .map(|d| d.data_dir().to_owned())
+        .unwrap_or_else(|| PathBuf::from("library"))
+}
+/// Platform config directory for the app, `./config` if it can't be determined
+#[inline]
+pub fn config_dir() -> PathBuf {
+    ProjectDirs::from("", "", APPNAME)
+        .map(|d| d.config_dir().to_owned())
+        .unwrap_or_else(|| PathBuf::from("config"))
+}
@@ -1,12 +1,14 @@
 #![feature(with_options)]
 
+pub mod error;
+pub mod export;
 pub mod funcs;
 pub mod library;
 pub mod reader;
 pub mod retriever;
 pub mod ui;
 
-pub use self::{funcs::*, library::*, retriever::*};
+pub use self::{error::Error, funcs::*, library::*, retriever::*};
 
 pub static APPNAME: &str = "pagepal";
 #[tokio::test]
@@ -14,7 +16,10 @@ async fn base() {
     use self::*;
     const TEST: &str = "https://readmanganato.com/manga-lt989154/chapter-21";
     let r = Retriever::default();
-    let c: Book<Manga> = r.book(TEST.into()).await;
-    c.save();
+    let mut c: Book<Manga> = r.book(TEST.into()).await.unwrap();
+    let dir = std::env::temp_dir().join("pagepal_base");
+    c.save(&mut Store::open(&Store::root(&dir)).unwrap())
+        .unwrap();
     println!("{:?}", c.content.len());
+    std::fs::remove_dir_all(&dir).ok();
 }
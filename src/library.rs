@@ -1,16 +1,55 @@
-use crate::{Page, Retriever};
+use crate::{data_dir, Error, Job, Page, Queue, Retriever, Update};
 use serde::{de::DeserializeOwned as deso, Deserialize as des, Serialize as ser};
 use serde_with::serde_as;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, ErrorKind::NotFound, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use url::Url;
 
 pub mod book;
+#[cfg(feature = "catalog")] pub mod catalog;
 pub mod chapter;
 pub mod content;
+pub mod format;
 pub mod id;
+pub mod import;
+pub mod metadata;
+pub mod search;
+pub mod store;
+pub mod transcode;
 
-#[allow(unused)] pub use self::{book::*, chapter::*, content::*};
+#[allow(unused)]
+pub use self::{
+    book::*,
+    chapter::*,
+    content::*,
+    format::*,
+    metadata::*,
+    search::*,
+    store::*,
+    transcode::*,
+};
+#[cfg(feature = "catalog")] pub use catalog::Catalog;
 pub use content::{Manga, Novel};
 
+/// Version of the on-disk library manifest, bump on breaking changes
+pub const MANIFEST_VERSION: u32 = 1;
+static MANIFEST: &str = "library.json";
+
+#[derive(ser, des)]
+struct Manifest<L> {
+    version: u32,
+    library: L,
+}
+#[derive(des)]
+struct ManifestVersion {
+    version: u32,
+}
+
 #[serde_as]
 #[derive(Default, Debug, Clone, ser, des)]
 pub struct Library<T = Novel, S = Manga>
@@ -18,10 +57,141 @@ where
     T: Media + std::fmt::Debug,
     S: Media + std::fmt::Debug, {
     #[serde_as(as = "Vec<(_, _)>")]
-    pub novels: HashMap<Label, Book<T>>,
+    pub novels:       HashMap<Label, Book<T>>,
     #[serde_as(as = "Vec<(_, _)>")]
-    pub manga:  HashMap<Label, Book<S>>,
-    r:          Retriever,
+    pub manga:        HashMap<Label, Book<S>>,
+    r:                Retriever,
+    /// Directory of the manifest the library was loaded from, the data dir
+    /// if it wasn't
+    #[serde(skip)]
+    pub(crate) dir:   Option<PathBuf>,
+    #[serde(skip)]
+    pub(crate) blobs: Option<Store>,
+    #[serde(skip)]
+    index:            Option<Index>,
+}
+
+/// A book found behind a url without its chapters and the links to them,
+/// see `Library::plan`
+#[derive(Debug)]
+pub enum Plan<T: Media, S: Media> {
+    Novel(Book<T>, Vec<Url>),
+    Manga(Book<S>, Vec<Url>),
+}
+impl<T: Media, S: Media> Plan<T, S> {
+    pub fn title(&self) -> &Label {
+        match self {
+            Plan::Novel(b, _) => &b.title,
+            Plan::Manga(b, _) => &b.title,
+        }
+    }
+
+    pub fn visual(&self) -> bool { matches!(self, Plan::Manga(..)) }
+
+    pub fn chapters(&self) -> usize {
+        match self {
+            Plan::Novel(_, links) | Plan::Manga(_, links) => links.len(),
+        }
+    }
+}
+
+/// The chapters of a planned book and the downloads of their content, see
+/// `Library::find`
+#[derive(Debug)]
+pub enum Found<T: Media, S: Media> {
+    Novel(Label, Vec<Chapter<T>>, Vec<Job>),
+    Manga(Label, Vec<Chapter<S>>, Vec<Job>),
+}
+impl<T: Media, S: Media> Found<T, S> {
+    pub fn jobs(&self) -> &[Job] {
+        match self {
+            Found::Novel(_, _, j) | Found::Manga(_, _, j) => j,
+        }
+    }
+}
+
+/// Content downloaded away from the library, e.g. on a task of its own, to
+/// be filed with `Library::receive`
+#[derive(Debug)]
+pub enum Fetched<T: Media, S: Media> {
+    Novel(Job, Result<Content<T>, Error>),
+    Manga(Job, Result<Content<S>, Error>),
+}
+
+/// The library's blob store, opened on first use next to its manifest in
+/// `dir`
+pub(crate) fn blobs<'a>(
+    blobs: &'a mut Option<Store>, dir: &Option<PathBuf>,
+) -> Result<&'a mut Store, Error> {
+    match blobs {
+        Some(s) => Ok(s),
+        None => {
+            let dir = dir.clone().unwrap_or_else(data_dir);
+            Ok(blobs.insert(Store::open(&Store::root(&dir))?))
+        }
+    }
+}
+
+/// The library's full-text index, opened on first use next to its manifest
+/// in `dir`
+fn text_index<'a>(
+    index: &'a mut Option<Index>, dir: &Option<PathBuf>,
+) -> Result<&'a mut Index, Error> {
+    match index {
+        Some(i) => Ok(i),
+        None => {
+            let dir = dir.clone().unwrap_or_else(data_dir);
+            Ok(index.insert(Index::open(&Index::root(&dir))?))
+        }
+    }
+}
+
+/// How long the index may go without being written while downloads are
+/// filed
+const INDEX_SYNC: Duration = Duration::from_secs(10);
+
+/// Puts the content `nums` just downloaded into `book` in the blob store, if
+/// it isn't yet, and indexes the text of novels. Whatever way content is
+/// downloaded, it's kept through here.
+fn keep<M: Media>(
+    book: &mut Book<M>, nums: Vec<Num>, store: &mut Store, index: &mut Index,
+) -> Result<(), Error> {
+    for num in nums {
+        if let Some(c) = book.content.get_mut(&num) {
+            c.store(store)?;
+            if !M::visual() {
+                index.add_content(&book.title, &num, c);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the content of an imported book in one by one and keeps it, see
+/// `Library::import`
+fn imported<M: Media>(
+    book: &mut Book<M>, store: &mut Store, index: &mut Index,
+) -> Result<(), Error> {
+    let nums = book.content.keys().cloned().collect::<Vec<_>>();
+    for num in nums {
+        if let Some(c) = book.content.get_mut(&num) {
+            c.load_local()?;
+        }
+        keep(book, vec![num.clone()], store, index)?;
+        if let Some(c) = book.content.get_mut(&num) {
+            c.unload();
+        }
+    }
+    Ok(())
+}
+
+/// Content of the chapters an update added to `book`
+fn added<M: Media>(book: &Book<M>, u: &Update) -> Vec<Num> {
+    book.content
+        .iter()
+        .filter(|(_, c)| c.ch.map_or(false, |ch| u.added.contains(&ch)))
+        .map(|(num, _)| num.to_owned())
+        .collect()
 }
 
 impl<
@@ -29,16 +199,345 @@ impl<
     S: std::fmt::Debug + Media + deso + Clone,
 > Library<T, S>
 {
-    pub async fn from_url<Z: Media + Clone>(&mut self, url: String) {
-        let page = Page::from(url);
-        self.r.refresh(&page).await;
-        let b = &page.check_visual().unwrap();
-        if *b {
-            let book = self.r.book(page).await;
-            self.add_manga(book);
+    pub async fn from_url<Z: Media + Clone>(
+        &mut self, url: String,
+    ) -> Result<(), Error> {
+        let page: Page = url.parse()?;
+        self.r.refresh(&page).await?;
+        let store = blobs(&mut self.blobs, &self.dir)?;
+        let index = text_index(&mut self.index, &self.dir)?;
+        if page.check_visual().unwrap_or_default() {
+            let mut book: Book<S> = self.r.book(page).await?;
+            self.fresh(&book.title, &book.index.loc)?;
+            let nums = book.content.keys().cloned().collect();
+            keep(&mut book, nums, store, index)?;
+            self.manga.insert(book.title.clone(), book);
         } else {
-            let book = self.r.book(page).await;
-            self.add_novel(book);
+            let mut book: Book<T> = self.r.book(page).await?;
+            self.fresh(&book.title, &book.index.loc)?;
+            let nums = book.content.keys().cloned().collect();
+            keep(&mut book, nums, store, index)?;
+            self.novels.insert(book.title.clone(), book);
+        }
+        index.flush()?;
+        store.flush()
+    }
+
+    /// Adds the book behind `url` without its content and queues the content
+    /// for download with `Library::resume`
+    pub async fn enqueue(
+        &mut self, url: String, queue: &mut Queue,
+    ) -> Result<Label, Error> {
+        let plan = Self::plan(&self.r, url).await?;
+        let (title, links) = self.add_plan(plan)?;
+        let visual = self.manga.contains_key(&title);
+        let found = Self::find(&self.r, title.to_owned(), links, visual).await;
+        self.add_chapters(found, queue)?;
+        Ok(title)
+    }
+
+    /// Finds out whether the book behind `url` is a novel or manga and how
+    /// many chapters it has, loading only its index page. Takes the
+    /// retriever rather than the library so it can be spawned with a copy of
+    /// `Library::retriever`.
+    pub async fn plan(r: &Retriever, url: String) -> Result<Plan<T, S>, Error> {
+        let page: Page = url.parse()?;
+        r.refresh(&page).await?;
+        Ok(match page.check_visual().unwrap_or_default() {
+            true => {
+                let (book, links) = r.plan::<S>(page).await?;
+                Plan::Manga(book, links)
+            }
+            false => {
+                let (book, links) = r.plan::<T>(page).await?;
+                Plan::Novel(book, links)
+            }
+        })
+    }
+
+    /// Adds a planned book without its chapters, returning its title and the
+    /// links to find them at with `Library::find`. A book that's in the
+    /// library already is kept as it is, see `Library::update` for new
+    /// chapters.
+    pub fn add_plan(
+        &mut self, plan: Plan<T, S>,
+    ) -> Result<(Label, Vec<Url>), Error> {
+        match plan {
+            Plan::Novel(book, links) => {
+                self.fresh(&book.title, &book.index.loc)?;
+                let title = book.title.to_owned();
+                self.add_novel(book);
+                Ok((title, links))
+            }
+            Plan::Manga(book, links) => {
+                self.fresh(&book.title, &book.index.loc)?;
+                let title = book.title.to_owned();
+                self.add_manga(book);
+                Ok((title, links))
+            }
+        }
+    }
+
+    /// Fails if a book titled `title` or with its index at `index` is in
+    /// the library already
+    fn fresh(&self, title: &Label, index: &Url) -> Result<(), Error> {
+        let found = self
+            .novels
+            .values()
+            .map(|b| (&b.title, &b.index.loc))
+            .chain(self.manga.values().map(|b| (&b.title, &b.index.loc)))
+            .find(|(t, loc)| *t == title || *loc == index);
+        match found {
+            Some((t, _)) => Err(Error::Exists(format!("book {}", t.0.trim()))),
+            None => Ok(()),
+        }
+    }
+
+    /// Loads the chapters of a book added with `Library::add_plan` and finds
+    /// the content in them, to be filed with `Library::add_chapters`. Takes
+    /// the retriever like `Library::plan`.
+    pub async fn find(
+        r: &Retriever, title: Label, links: Vec<Url>, visual: bool,
+    ) -> Found<T, S> {
+        match visual {
+            true => {
+                let (chs, jobs) = r.chapter_jobs::<S>(&title, 0, links).await;
+                Found::Manga(title, chs, jobs)
+            }
+            false => {
+                let (chs, jobs) = r.chapter_jobs::<T>(&title, 0, links).await;
+                Found::Novel(title, chs, jobs)
+            }
+        }
+    }
+
+    /// Adds the chapters found for a planned book and queues the downloads
+    /// of their content
+    pub fn add_chapters(
+        &mut self, found: Found<T, S>, queue: &mut Queue,
+    ) -> Result<(), Error> {
+        let missing = |title: &Label| Error::Missing(format!("book {}", title.0));
+        let jobs = match found {
+            Found::Novel(title, chs, jobs) => {
+                let book =
+                    self.novels.get_mut(&title).ok_or_else(|| missing(&title))?;
+                book.chs.extend(chs.into_iter().map(|a| (a.id, a)));
+                jobs
+            }
+            Found::Manga(title, chs, jobs) => {
+                let book =
+                    self.manga.get_mut(&title).ok_or_else(|| missing(&title))?;
+                book.chs.extend(chs.into_iter().map(|a| (a.id, a)));
+                jobs
+            }
+        };
+        // The book has to be in the manifest before its jobs are queued, or
+        // they would be dropped on the next start
+        self.checkpoint()?;
+        queue.extend(jobs)
+    }
+
+    /// A copy of the retriever sharing its rate limits and site state, for
+    /// retrieving on other tasks
+    pub fn retriever(&self) -> Retriever { self.r.clone() }
+
+    /// Files content downloaded with `Retriever::fetch` under its book and
+    /// records the jobs in `queue`, only once the manifest holds the content
+    /// so a crash can't lose it. Returns the jobs and whether they succeeded.
+    pub fn receive(
+        &mut self, fetched: Vec<Fetched<T, S>>, queue: &mut Queue,
+    ) -> Result<Vec<(Job, bool)>, Error> {
+        if fetched.is_empty() {
+            return Ok(vec![]);
+        }
+        let store = blobs(&mut self.blobs, &self.dir)?;
+        let index = text_index(&mut self.index, &self.dir)?;
+        let mut done = vec![];
+        for f in fetched {
+            done.push(match f {
+                Fetched::Novel(job, res) => {
+                    match (self.novels.get_mut(&job.book), res) {
+                        (Some(book), Ok(c)) => {
+                            let num = book.file(job.chapter, job.n, c, store)?;
+                            let ok = num.is_some();
+                            keep(book, num.into_iter().collect(), store, index)?;
+                            (job, ok)
+                        }
+                        _ => (job, false),
+                    }
+                }
+                Fetched::Manga(job, res) => {
+                    match (self.manga.get_mut(&job.book), res) {
+                        (Some(book), Ok(c)) => {
+                            let num = book.file(job.chapter, job.n, c, store)?;
+                            let ok = num.is_some();
+                            keep(book, num.into_iter().collect(), store, index)?;
+                            (job, ok)
+                        }
+                        _ => (job, false),
+                    }
+                }
+            });
+        }
+        index.sync(INDEX_SYNC)?;
+        store.flush()?;
+        self.checkpoint()?;
+        done.iter()
+            .try_for_each(|(job, ok)| queue.finish(job, *ok))?;
+        Ok(done)
+    }
+
+    /// Runs the queued downloads of every book in the library, picking up
+    /// where an interrupted run stopped. The jobs of a book are logged as
+    /// done once the manifest holds their content.
+    pub async fn resume(&mut self, queue: &mut Queue) -> Result<usize, Error> {
+        let mut done = 0;
+        for label in queue.books() {
+            let jobs = queue.pending(&label);
+            let store = blobs(&mut self.blobs, &self.dir)?;
+            let ran = if let Some(book) = self.novels.get_mut(&label) {
+                let ran = self.r.run(book, jobs, store).await?;
+                let index = text_index(&mut self.index, &self.dir)?;
+                index.add_book(book);
+                index.flush()?;
+                ran
+            } else if let Some(book) = self.manga.get_mut(&label) {
+                self.r.run(book, jobs, store).await?
+            } else {
+                continue;
+            };
+            self.checkpoint()?;
+            for (job, ok) in ran {
+                done += ok as usize;
+                queue.finish(&job, ok)?;
+            }
+        }
+        queue.store()?;
+        Ok(done)
+    }
+
+    /// Adds the book in a local folder, archive or file, as manga if it's
+    /// mostly images and as a novel otherwise. Its pages are copied into the
+    /// blob store, so the originals can be moved or deleted afterwards.
+    pub fn import(&mut self, path: &Path) -> Result<Label, Error> {
+        let store = blobs(&mut self.blobs, &self.dir)?;
+        let index = text_index(&mut self.index, &self.dir)?;
+        let title = match import::is_visual(path)? {
+            true => {
+                let mut book = import::book::<S>(path)?;
+                imported(&mut book, store, index)?;
+                let title = book.title.to_owned();
+                self.manga.insert(title.clone(), book);
+                title
+            }
+            false => {
+                let mut book = import::book::<T>(path)?;
+                imported(&mut book, store, index)?;
+                let title = book.title.to_owned();
+                self.novels.insert(title.clone(), book);
+                title
+            }
+        };
+        index.flush()?;
+        store.flush()?;
+        Ok(title)
+    }
+
+    /// Titles of the books passing the filter, in order
+    pub fn filter(&self, f: &Filter) -> Vec<Label> {
+        let mut found = self
+            .novels
+            .iter()
+            .map(|(l, b)| (l, &b.meta))
+            .chain(self.manga.iter().map(|(l, b)| (l, &b.meta)))
+            .filter(|(l, meta)| meta.matches(l, f))
+            .map(|(l, _)| l.to_owned())
+            .collect::<Vec<_>>();
+        found.sort();
+        found
+    }
+
+    /// The covers named in the metadata of books that have none yet, with
+    /// the book and where the cover goes
+    pub fn missing_covers(&self) -> Vec<(Label, Page, PathBuf)> {
+        self.novels
+            .values()
+            .map(|b| (&b.title, &b.meta, b.cover_path()))
+            .chain(
+                self.manga
+                    .values()
+                    .map(|b| (&b.title, &b.meta, b.cover_path())),
+            )
+            .filter_map(|(title, m, path)| {
+                let page = Page::from(m.cover.as_ref()?.as_str());
+                Some((title.to_owned(), page, path))
+            })
+            .filter(|(_, _, path)| !path.exists())
+            .collect()
+    }
+
+    /// Downloads the `wanted` covers, see `Library::missing_covers`, handing
+    /// the book of every saved cover to `f`. Covers that fail to download
+    /// are tried again next time. Takes the retriever like `Library::plan`,
+    /// so it doesn't hold up the library.
+    pub async fn fetch_covers(
+        r: &Retriever, wanted: Vec<(Label, Page, PathBuf)>,
+        mut f: impl FnMut(Result<Label, Error>),
+    ) {
+        for (title, page, path) in wanted {
+            if let Ok((data, _)) = r.image(&page).await {
+                let saved = path
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(path, data));
+                f(saved.map(|_| title).map_err(Into::into));
+            }
+        }
+    }
+
+    /// Registers the site definitions found in `dir`, see
+    /// `Retriever::load_sites`
+    pub fn load_sites(
+        &mut self, dir: &Path,
+    ) -> Result<(usize, Vec<(PathBuf, Error)>), Error> {
+        self.r.load_sites(dir)
+    }
+
+    /// Checks every book for new chapters and downloads only those. Fails
+    /// only if the new content can't be kept, not when a book does.
+    pub async fn update(
+        &mut self,
+    ) -> Result<HashMap<Label, Result<Update, Error>>, Error> {
+        let store = blobs(&mut self.blobs, &self.dir)?;
+        let index = text_index(&mut self.index, &self.dir)?;
+        let mut report = HashMap::new();
+        for (label, book) in self.novels.iter_mut() {
+            let res = self.r.update(book).await;
+            if let Ok(u) = &res {
+                let nums = added(book, u);
+                keep(book, nums, store, index)?;
+            }
+            report.insert(label.to_owned(), res);
+        }
+        for (label, book) in self.manga.iter_mut() {
+            let res = self.r.update(book).await;
+            if let Ok(u) = &res {
+                let nums = added(book, u);
+                keep(book, nums, store, index)?;
+            }
+            report.insert(label.to_owned(), res);
+        }
+        index.flush()?;
+        store.flush()?;
+        Ok(report)
+    }
+
+    /// Writes the index changes `Library::receive` held back, before the
+    /// library is stored
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.index {
+            Some(i) if i.changed() => i.flush(),
+            _ => Ok(()),
         }
     }
 
@@ -50,18 +549,247 @@ impl<
         self.novels.insert(book.title.clone(), book)
     }
 
-    pub fn rename_novel(&mut self, idx: &Label, name: String) {
+    pub fn rename_novel(
+        &mut self, idx: &Label, name: String,
+    ) -> Result<(), Error> {
         match self.novels.remove(idx) {
             Some(mut b) => {
                 b.title = name.clone().into();
-                self.novels.insert(name.into(), b)
+                self.novels.insert(name.clone().into(), b);
+                let index = text_index(&mut self.index, &self.dir)?;
+                index.rename_book(idx, &name.into());
+                index.flush()
             }
-            None => todo!(),
-        };
+            None => Err(Error::Missing(format!("novel {}", idx.0))),
+        }
     }
 
-    pub fn save(&self) {
-        self.manga.values().into_iter().for_each(|b| b.save());
-        self.novels.values().into_iter().for_each(|b| b.save());
+    /// Puts the loaded content of every book into the blob store and indexes
+    /// the text of the novels
+    pub fn save(&mut self) -> Result<(), Error> {
+        let store = blobs(&mut self.blobs, &self.dir)?;
+        let index = text_index(&mut self.index, &self.dir)?;
+        self.manga.values_mut().try_for_each(|b| b.save(store))?;
+        self.novels.values_mut().try_for_each(|b| b.save(store))?;
+        self.novels.values().for_each(|b| {
+            index.add_book(b);
+        });
+        index.flush()?;
+        store.flush()
     }
+
+    /// Finds passages of the novels matching the words and `"quoted
+    /// phrases"` of the query, best matches first
+    pub fn search_text(&mut self, query: &str) -> Result<Vec<Hit>, Error> {
+        let novels = &self.novels;
+        let store = blobs(&mut self.blobs, &self.dir)?;
+        Ok(text_index(&mut self.index, &self.dir)?
+            .search(query)
+            .into_iter()
+            .filter_map(|mut hit| {
+                let book = novels.get(&hit.book)?;
+                let text =
+                    book.content.get(&hit.num)?.bytes(&book.dir(), store).ok()?;
+                hit.snippet =
+                    snippet(&String::from_utf8_lossy(&text), hit.offset, 160);
+                Some(hit)
+            })
+            .collect())
+    }
+
+    /// Removes a book, deleting the blobs no other content refers to
+    pub fn remove(&mut self, label: &Label) -> Result<(), Error> {
+        let store = blobs(&mut self.blobs, &self.dir)?;
+        match (self.novels.remove(label), self.manga.remove(label)) {
+            (None, None) => {
+                return Err(Error::Missing(format!("book {}", label.0)))
+            }
+            (novel, manga) => {
+                if let Some(b) = novel {
+                    b.release(store)?;
+                    let index = text_index(&mut self.index, &self.dir)?;
+                    index.remove_book(label);
+                    index.flush()?;
+                }
+                if let Some(b) = manga {
+                    b.release(store)?;
+                }
+            }
+        }
+        store.flush()
+    }
+
+    /// The blob store the content of the books is in
+    pub fn blobs(&mut self) -> Result<&mut Store, Error> {
+        blobs(&mut self.blobs, &self.dir)
+    }
+
+    /// Rehashes the stored blobs, returning the missing or corrupt ones
+    pub fn verify(&mut self) -> Result<Vec<String>, Error> {
+        blobs(&mut self.blobs, &self.dir)?.verify()
+    }
+
+    /// Reads the whole catalog back from the manifest in `dir`, its content
+    /// is read from the blob store next to it
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let bytes = fs::read(dir.join(MANIFEST))?;
+        match serde_json::from_slice::<ManifestVersion>(&bytes)?.version {
+            MANIFEST_VERSION => {
+                let mut m: Manifest<Self> = serde_json::from_slice(&bytes)?;
+                m.library.dir = Some(dir.to_owned());
+                Ok(m.library)
+            }
+            v => Err(Error::Parse(format!("library manifest version {}", v))),
+        }
+    }
+
+    /// Like `Library::load`, but an empty library if there's no manifest in
+    /// `dir` yet. A manifest that can't be read is an error rather than an
+    /// empty library, so it doesn't get stored over.
+    pub fn open(dir: &Path) -> Result<Self, Error> {
+        match Self::load(dir) {
+            Err(Error::Io(e)) if e.kind() == NotFound => Ok(Self {
+                dir: Some(dir.to_owned()),
+                ..Default::default()
+            }),
+            res => res,
+        }
+    }
+
+    /// Stores the manifest where the library was loaded from, see
+    /// `Library::receive`
+    fn checkpoint(&self) -> Result<(), Error> {
+        self.store(&self.dir.clone().unwrap_or_else(data_dir))
+    }
+
+    /// Writes the whole catalog (books, chapters, content keys and read
+    /// positions) to a manifest in `dir`, replacing the previous one
+    pub fn store(&self, dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(MANIFEST);
+        let tmp = path.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut w, &Manifest {
+            version: MANIFEST_VERSION,
+            library: self,
+        })?;
+        w.flush()?;
+        Ok(fs::rename(tmp, path)?)
+    }
+}
+
+#[test]
+fn library_roundtrip() {
+    let dir = std::env::temp_dir().join("pagepal_library_roundtrip");
+    let mut lib: Library = Library::default();
+    let book = Book {
+        title: Label("Test".to_string()),
+        index: Page::from("https://example.com/novel/test"),
+        pos: 7,
+        ..Default::default()
+    };
+    lib.add_novel(book.clone());
+    lib.store(&dir).unwrap();
+    let loaded: Library = Library::load(&dir).unwrap();
+    assert_eq!(loaded.novels.get(&book.title), Some(&book));
+    assert!(loaded.manga.is_empty());
+    assert_eq!(loaded.dir.as_deref(), Some(dir.as_path()));
+
+    fs::write(dir.join(MANIFEST), r#"{"version": 99, "library": {}}"#).unwrap();
+    assert!(Library::<Novel, Manga>::open(&dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(Library::<Novel, Manga>::open(&dir)
+        .unwrap()
+        .novels
+        .is_empty());
+}
+
+#[test]
+fn import_stores() {
+    let dir = std::env::temp_dir().join("pagepal_import_stores");
+    let src = dir.join("A novel");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("Chapter 1.txt"), "The fox jumps.").unwrap();
+    let mut lib: Library = Library::open(&dir.join("library")).unwrap();
+    let title = lib.import(&src).unwrap();
+    fs::remove_dir_all(&src).unwrap();
+    let book = &lib.novels[&title];
+    let c = book.content.values().next().unwrap();
+    assert!(c.data().get().is_empty() && c.hashes.len() == 1);
+    let hits = lib.search_text("fox").unwrap();
+    assert_eq!(hits[0].snippet, "The fox jumps.");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn update_after_reload() {
+    let dir = std::env::temp_dir().join("pagepal_update_after_reload");
+    let chapter =
+        |s: &str| format!("<html><body><div><p>{}</p></div></body></html>", s);
+    let base = crate::retriever::serve(vec![
+        (
+            "/novel/test",
+            "<html><head><title>Test</title></head><body><div><ul><li><a \
+             href=\"/novel/test/chapter-1\">1</a></li></ul></div></body></html>"
+                .to_string(),
+        ),
+        ("/novel/test/chapter-1", chapter("The fox jumps.")),
+    ]);
+    let mut lib: Library = Library::open(&dir).unwrap();
+    lib.add_novel(Book {
+        title: Label("Test".to_string()),
+        index: Page::from(base.join("novel/test").unwrap().as_str()),
+        ..Default::default()
+    });
+    lib.store(&dir).unwrap();
+    let mut lib: Library = Library::load(&dir).unwrap();
+    let report = lib.update().await.unwrap();
+    let update = report[&Label("Test".to_string())].as_ref().unwrap();
+    assert_eq!(update.added, vec![0]);
+    assert_eq!(lib.search_text("fox").unwrap()[0].snippet, "The fox jumps.");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn receive_stores_first() {
+    let dir = std::env::temp_dir().join("pagepal_receive_stores_first");
+    let mut lib: Library = Library::open(&dir).unwrap();
+    let mut queue = Queue::open(&dir.join("queue.json")).unwrap();
+    let title = Label("Test".to_string());
+    lib.add_novel(Book {
+        title: title.clone(),
+        ..Default::default()
+    });
+    let url: Url = "https://example.com/novel/test/chapter-1".parse().unwrap();
+    let job = Job::new(title.clone(), 0, 0, url.clone());
+    queue.extend(vec![job.clone()]).unwrap();
+    let text = Content::from("The fox jumps.".to_string());
+    let c = Content::from((Page::from(url.as_str()), text));
+    let done = lib.receive(vec![Fetched::Novel(job, Ok(c))], &mut queue);
+    assert_eq!(done.unwrap().len(), 1);
+    assert!(queue.pending(&title).is_empty());
+    // The manifest held the content before the job was logged as done
+    let loaded: Library = Library::load(&dir).unwrap();
+    assert_eq!(loaded.novels[&title].content.len(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn add_existing() {
+    let mut lib: Library = Library::default();
+    let book = |title: &str, index: &str| Book {
+        title: Label(title.to_string()),
+        index: Page::from(index),
+        pos: 7,
+        ..Default::default()
+    };
+    let plan = |b: Book<Novel>| Plan::<Novel, Manga>::Novel(b, vec![]);
+    let index = "https://example.com/novel/test";
+    lib.add_plan(plan(book("Test", index))).unwrap();
+    assert!(lib
+        .add_plan(plan(book("Test", "https://a.com/test")))
+        .is_err());
+    assert!(lib.add_plan(plan(book("Other", index))).is_err());
+    assert_eq!(lib.novels.len(), 1);
+    assert_eq!(lib.novels[&Label("Test".to_string())].pos, 7);
 }
@@ -1,7 +1,18 @@
-use crate::{Chapter, Content, Media, Num, Page};
+use crate::{
+    Chapter,
+    Content,
+    Error,
+    Media,
+    Metadata,
+    Num,
+    Page,
+    Store,
+    Transcode,
+};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize as des, Serialize as ser};
 use serde_with::serde_as;
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, convert::TryFrom, path::PathBuf};
 use tokio::macros::support::thread_rng_n;
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Debug, des, ser)]
@@ -9,21 +20,100 @@ pub struct Label(pub String);
 #[serde_as]
 #[derive(Clone, Default, Debug, ser, des)]
 pub struct Book<T: Media> {
-    pub title:   Label,
-    pub index:   Page,
+    pub title:     Label,
+    pub index:     Page,
     #[serde_as(as = "Vec<(_, _)>")]
-    pub chs:     BTreeMap<u16, Chapter<T>>,
+    pub chs:       BTreeMap<u16, Chapter<T>>,
     #[serde_as(as = "Vec<(_, _)>")]
-    pub content: BTreeMap<Num, Content<T>>,
-    pub pos:     u32,
+    pub content:   BTreeMap<Num, Content<T>>,
+    /// Page of a manga or content of a novel being read
+    pub pos:       u32,
+    /// Byte offset of the read position in the text at `pos`
+    #[serde(default)]
+    pub offset:    u32,
+    /// Re-encoding applied to downloaded images
+    #[serde(default)]
+    pub transcode: Option<Transcode>,
+    #[serde(default)]
+    pub meta:      Metadata,
+    /// When the read position last changed
+    #[serde(default)]
+    pub last_read: Option<DateTime<Utc>>,
 }
 
 impl<T: Media> Book<T> {
-    pub fn save(&self) {
-        use std::path::PathBuf;
+    /// Directory the content of the book is saved to
+    pub fn dir(&self) -> PathBuf {
         static LIBRARY: &str = "library";
-        let pb = PathBuf::from(LIBRARY).join(self.title.0.trim());
-        self.content.iter().for_each(move |(_, a)| a.save(&pb));
+        PathBuf::from(LIBRARY).join(self.title.0.trim())
+    }
+
+    /// Cover image fetched from `Metadata::cover`
+    pub fn cover_path(&self) -> PathBuf { self.dir().join("cover") }
+
+    /// Puts the loaded content into the blob store, imported files stay
+    /// where they are
+    pub fn save(&mut self, store: &mut Store) -> Result<(), Error> {
+        self.content.values_mut().try_for_each(|a| a.store(store))
+    }
+
+    /// Drops the book's references to its blobs, e.g. before removing it
+    pub fn release(&self, store: &mut Store) -> Result<(), Error> {
+        self.content.values().try_for_each(|a| a.release(store))
+    }
+
+    /// Where content `n` of chapter `ch` is filed, nothing past the 256
+    /// pages a chapter can hold
+    pub fn place(ch: u16, n: usize) -> Option<Num> {
+        Some(Num(ch, Some(u8::try_from(n).ok()?)))
+    }
+
+    /// Files content `n` of chapter `ch`, returning the content it replaces
+    pub fn add_content(
+        &mut self, ch: u16, n: usize, mut a: Content<T>,
+    ) -> Result<Option<Content<T>>, Error> {
+        let num = Self::place(ch, n).ok_or_else(|| {
+            Error::Parse(format!("page {} of chapter {}", n, ch))
+        })?;
+        a.ch = Some(ch);
+        Ok(self.content.insert(num, a))
+    }
+
+    /// Processes freshly downloaded content, puts it in the blob store and
+    /// files it as content `n` of chapter `ch`, releasing the blobs of the
+    /// content it replaces. Returns where it was filed, nothing if the
+    /// chapter can't hold it.
+    pub fn file(
+        &mut self, ch: u16, n: usize, mut a: Content<T>, store: &mut Store,
+    ) -> Result<Option<Num>, Error> {
+        let num = match Self::place(ch, n) {
+            Some(num) => num,
+            None => return Ok(None),
+        };
+        self.process(&mut a);
+        a.store(store)?;
+        if let Some(old) = self.add_content(ch, n, a)? {
+            old.release(store)?;
+        }
+        Ok(Some(num))
+    }
+
+    /// Content of chapter `ch` in reading order
+    pub fn chapter(&self, ch: u16) -> Vec<&Content<T>> {
+        self.content.values().filter(|a| a.ch == Some(ch)).collect()
+    }
+
+    /// Applies the book's transcoding to freshly downloaded content. Images
+    /// that can't be decoded are kept as they are.
+    pub fn process(&self, a: &mut Content<T>) {
+        if let Some(t) = &self.transcode {
+            a.transcode(t).ok();
+        }
+    }
+
+    /// Id for the next chapter added to the book
+    pub fn next_chapter(&self) -> u16 {
+        self.chs.keys().next_back().map_or(0, |k| k + 1)
     }
 }
 
@@ -39,3 +129,46 @@ impl Default for Label {
 impl From<String> for Label {
     fn from(s: String) -> Self { Label(s) }
 }
+
+#[test]
+fn refile_releases() {
+    use crate::Novel;
+    let dir = std::env::temp_dir().join("pagepal_refile_releases");
+    let mut store = Store::open(&dir).unwrap();
+    let mut book: Book<Novel> = Book::default();
+    let page = Page::from("https://example.com/novel/chapter-1");
+    let content = |text: &str| {
+        Content::from((page.clone(), Content::from(text.to_string())))
+    };
+    book.file(0, 0, content("first"), &mut store).unwrap();
+    book.file(0, 0, content("first"), &mut store).unwrap();
+    let first = Store::hash(b"first");
+    assert_eq!(store.refs(&first), 1);
+    book.file(0, 0, content("second"), &mut store).unwrap();
+    assert_eq!(store.refs(&first), 0);
+    assert!(!store.path(&first).exists());
+    assert_eq!(store.refs(&Store::hash(b"second")), 1);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn file_by_job() {
+    use crate::Novel;
+    let dir = std::env::temp_dir().join("pagepal_file_by_job");
+    let mut store = Store::open(&dir).unwrap();
+    let mut book: Book<Novel> = Book::default();
+    // Urls without numbers used to be filed under the same place
+    let content = |url: &str, text: &str| {
+        Content::from((Page::from(url), Content::from(text.to_string())))
+    };
+    let one = content("https://example.com/novel/the-start", "one");
+    let two = content("https://example.com/novel/the-end", "two");
+    let filed = book.file(0, 0, one, &mut store).unwrap();
+    assert_eq!(filed, Some(Num(0, Some(0))));
+    let filed = book.file(1, 0, two, &mut store).unwrap();
+    assert_eq!(filed, Some(Num(1, Some(0))));
+    assert_eq!(book.chapter(0).len() + book.chapter(1).len(), 2);
+    assert_eq!(store.refs(&Store::hash(b"one")), 1);
+    assert_eq!(Book::<Novel>::place(0, 256), None);
+    std::fs::remove_dir_all(&dir).ok();
+}
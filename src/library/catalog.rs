@@ -0,0 +1,414 @@
+use crate::{
+    Book,
+    Chapter,
+    Content,
+    Error,
+    Format,
+    Label,
+    Library,
+    Media,
+    Num,
+    Page,
+    Store,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::de::DeserializeOwned as deso;
+use std::{convert::TryFrom, fmt::Debug, path::Path};
+
+static SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+CREATE TABLE IF NOT EXISTS books (
+    id        INTEGER PRIMARY KEY,
+    label     TEXT NOT NULL UNIQUE,
+    visual    INTEGER NOT NULL,
+    source    TEXT NOT NULL,
+    pos       INTEGER NOT NULL DEFAULT 0,
+    offset    INTEGER NOT NULL DEFAULT 0,
+    last_read INTEGER,
+    transcode TEXT,
+    meta      TEXT NOT NULL DEFAULT '{}',
+    added     INTEGER NOT NULL,
+    updated   INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chapters (
+    book   INTEGER NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    id     INTEGER NOT NULL,
+    source TEXT,
+    number INTEGER,
+    sub    INTEGER,
+    PRIMARY KEY (book, id)
+);
+CREATE TABLE IF NOT EXISTS contents (
+    book    INTEGER NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    num     INTEGER NOT NULL,
+    sub     INTEGER NOT NULL,
+    id      INTEGER NOT NULL,
+    chapter INTEGER,
+    source  TEXT,
+    format  TEXT,
+    parts   INTEGER NOT NULL DEFAULT 0,
+    hashes  TEXT NOT NULL DEFAULT '[]',
+    PRIMARY KEY (book, num, sub)
+);
+CREATE INDEX IF NOT EXISTS contents_chapter ON contents (book, chapter);
+";
+
+static ENTRY: &str = "SELECT b.label, b.visual, b.source, b.pos, b.added, \
+                      b.updated, (SELECT COUNT(*) FROM chapters c WHERE c.book \
+                      = b.id) FROM books b";
+
+/// A book as listed in the catalog, without its chapters and content
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub label:    Label,
+    pub visual:   bool,
+    pub source:   String,
+    pub chapters: u32,
+    pub pos:      u32,
+    pub added:    DateTime<Utc>,
+    pub updated:  DateTime<Utc>,
+}
+impl Entry {
+    fn from_row(r: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            label:    Label(r.get(0)?),
+            visual:   r.get(1)?,
+            source:   r.get(2)?,
+            pos:      r.get(3)?,
+            added:    Utc.timestamp(r.get(4)?, 0),
+            updated:  Utc.timestamp(r.get(5)?, 0),
+            chapters: r.get(6)?,
+        })
+    }
+}
+
+/// Books kept in SQLite, so they can be listed, searched and loaded one at a
+/// time without reading the whole manifest. The `Library` still works on its
+/// in-memory maps, the catalog is a copy written with `Library::to_catalog`
+/// and read back with `Library::from_catalog`.
+pub struct Catalog {
+    db: Connection,
+}
+impl Catalog {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(db: Connection) -> Result<Self, Error> {
+        db.execute_batch(SCHEMA)?;
+        Ok(Self { db })
+    }
+
+    /// Adds a book or replaces the one with the same title
+    pub fn add<T: Media>(&mut self, book: &Book<T>) -> Result<(), Error> {
+        let now = Utc::now().timestamp();
+        let transcode = book
+            .transcode
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let meta = serde_json::to_string(&book.meta)?;
+        let tx = self.db.transaction()?;
+        tx.execute(
+            "INSERT INTO books (label, visual, source, pos, offset, \
+             transcode, meta, last_read, added, updated) VALUES (?1, ?2, ?3, \
+             ?4, ?5, ?6, ?7, ?8, ?9, ?9) ON CONFLICT (label) DO UPDATE SET \
+             visual = ?2, source = ?3, pos = ?4, offset = ?5, transcode = ?6, \
+             meta = ?7, last_read = ?8, updated = ?9",
+            params![
+                book.title.0,
+                T::visual(),
+                book.index.loc.as_str(),
+                book.pos,
+                book.offset,
+                transcode,
+                meta,
+                book.last_read.map(|a| a.timestamp()),
+                now
+            ],
+        )?;
+        let id: i64 = tx.query_row(
+            "SELECT id FROM books WHERE label = ?1",
+            params![book.title.0],
+            |r| r.get(0),
+        )?;
+        tx.execute("DELETE FROM chapters WHERE book = ?1", params![id])?;
+        tx.execute("DELETE FROM contents WHERE book = ?1", params![id])?;
+        {
+            let mut st = tx.prepare(
+                "INSERT INTO chapters (book, id, source, number, sub) VALUES \
+                 (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for ch in book.chs.values() {
+                st.execute(params![
+                    id,
+                    ch.id,
+                    ch.src.as_ref().map(|a| a.loc.as_str()),
+                    ch.number.as_ref().map(|a| a.0),
+                    ch.number.as_ref().and_then(|a| a.1)
+                ])?;
+            }
+            let mut st = tx.prepare(
+                "INSERT INTO contents (book, num, sub, id, chapter, source, \
+                 format, parts, hashes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, \
+                 ?9)",
+            )?;
+            for (num, c) in &book.content {
+                st.execute(params![
+                    id,
+                    num.0,
+                    num.1.map_or(-1, i64::from),
+                    c.id as i64,
+                    c.ch,
+                    c.src.as_ref().map(|a| a.loc.as_str()),
+                    c.format.map(|a| a.ext()),
+                    c.parts as i64,
+                    serde_json::to_string(&c.hashes)?
+                ])?;
+            }
+        }
+        Ok(tx.commit()?)
+    }
+
+    /// Loads a whole book
+    pub fn get<T: Media>(&self, label: &Label) -> Result<Option<Book<T>>, Error> {
+        let row = self
+            .db
+            .query_row(
+                "SELECT id, source, pos, offset, transcode, meta, last_read \
+                 FROM books WHERE label = ?1",
+                params![label.0],
+                |r| {
+                    Ok((
+                        r.get::<_, i64>(0)?,
+                        r.get::<_, String>(1)?,
+                        r.get::<_, u32>(2)?,
+                        r.get::<_, u32>(3)?,
+                        r.get::<_, Option<String>>(4)?,
+                        r.get::<_, String>(5)?,
+                        r.get::<_, Option<i64>>(6)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let (id, source, pos, offset, transcode, meta, read) = match row {
+            Some(a) => a,
+            None => return Ok(None),
+        };
+        let mut book = Book {
+            title: label.to_owned(),
+            index: Page::from(source),
+            pos,
+            offset,
+            transcode: transcode.map(|a| serde_json::from_str(&a)).transpose()?,
+            meta: serde_json::from_str(&meta)?,
+            last_read: read.map(|a| Utc.timestamp(a, 0)),
+            ..Default::default()
+        };
+        let mut st = self.db.prepare(
+            "SELECT id, source, number, sub FROM chapters WHERE book = ?1",
+        )?;
+        let chs = st.query_map(params![id], |r| {
+            Ok((
+                r.get::<_, u16>(0)?,
+                r.get::<_, Option<String>>(1)?,
+                r.get::<_, Option<u16>>(2)?,
+                r.get::<_, Option<u8>>(3)?,
+            ))
+        })?;
+        for ch in chs {
+            let (ch, src, number, sub) = ch?;
+            book.chs.insert(ch, Chapter {
+                id: ch,
+                src: src.map(Page::from),
+                number: number.map(|a| Num(a, sub)),
+                ..Default::default()
+            });
+        }
+        let mut st = self.db.prepare(
+            "SELECT num, sub, id, chapter, source, format, parts, hashes FROM \
+             contents WHERE book = ?1",
+        )?;
+        let rows = st.query_map(params![id], |r| {
+            Ok((
+                Num(r.get(0)?, u8::try_from(r.get::<_, i64>(1)?).ok()),
+                r.get::<_, i64>(2)?,
+                r.get::<_, Option<u16>>(3)?,
+                r.get::<_, Option<String>>(4)?,
+                r.get::<_, Option<String>>(5)?,
+                r.get::<_, i64>(6)?,
+                r.get::<_, String>(7)?,
+            ))
+        })?;
+        for row in rows {
+            let (num, cid, ch, src, format, parts, hashes) = row?;
+            let mut c = Content::default();
+            c.id = cid as u64;
+            c.ch = ch;
+            c.src = src.map(Page::from);
+            c.format = format.as_deref().and_then(Format::from_ext);
+            c.parts = parts as usize;
+            c.hashes = serde_json::from_str(&hashes)?;
+            book.content.insert(num, c);
+        }
+        Ok(Some(book))
+    }
+
+    pub fn rename(&mut self, label: &Label, name: String) -> Result<(), Error> {
+        match self.db.execute(
+            "UPDATE books SET label = ?2, updated = ?3 WHERE label = ?1",
+            params![label.0, name, Utc::now().timestamp()],
+        )? {
+            0 => Err(Error::Missing(format!("book {}", label.0))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores the read position, `offset` is only used by novels
+    pub fn set_pos(
+        &mut self, label: &Label, pos: u32, offset: u32,
+    ) -> Result<(), Error> {
+        match self.db.execute(
+            "UPDATE books SET pos = ?2, offset = ?3 WHERE label = ?1",
+            params![label.0, pos, offset],
+        )? {
+            0 => Err(Error::Missing(format!("book {}", label.0))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes a book, releasing its blobs in `store`
+    pub fn remove(
+        &mut self, label: &Label, store: &mut Store,
+    ) -> Result<(), Error> {
+        let hashes = {
+            let mut st = self.db.prepare(
+                "SELECT c.hashes FROM contents c JOIN books b ON c.book = b.id \
+                 WHERE b.label = ?1",
+            )?;
+            let rows =
+                st.query_map(params![label.0], |r| r.get::<_, String>(0))?;
+            rows.map(|a| {
+                Ok::<_, Error>(serde_json::from_str::<Vec<String>>(&a?)?)
+            })
+            .collect::<Result<Vec<_>, Error>>()?
+        };
+        if self
+            .db
+            .execute("DELETE FROM books WHERE label = ?1", params![label.0])? ==
+            0
+        {
+            return Err(Error::Missing(format!("book {}", label.0)));
+        }
+        for hash in hashes.iter().flatten() {
+            store.release(hash)?;
+        }
+        store.flush()
+    }
+
+    /// Every book, or only the manga or only the novels
+    pub fn list(&self, visual: Option<bool>) -> Result<Vec<Entry>, Error> {
+        let mut st = self.db.prepare(&format!(
+            "{} WHERE ?1 IS NULL OR b.visual = ?1 ORDER BY b.label",
+            ENTRY
+        ))?;
+        let rows = st.query_map(params![visual], Entry::from_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Books whose title or source contains `query`, ignoring case
+    pub fn search(&self, query: &str) -> Result<Vec<Entry>, Error> {
+        let pattern = format!(
+            "%{}%",
+            query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let mut st = self.db.prepare(&format!(
+            "{} WHERE b.label LIKE ?1 ESCAPE '\\' OR b.source LIKE ?1 ESCAPE \
+             '\\' ORDER BY b.label",
+            ENTRY
+        ))?;
+        let rows = st.query_map(params![pattern], Entry::from_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+impl<T: Debug + Media + deso + Clone, S: Debug + Media + deso + Clone>
+    Library<T, S>
+{
+    /// Writes every book of the library into `catalog`
+    pub fn to_catalog(&self, catalog: &mut Catalog) -> Result<(), Error> {
+        self.novels.values().try_for_each(|b| catalog.add(b))?;
+        self.manga.values().try_for_each(|b| catalog.add(b))
+    }
+
+    /// Loads every book in `catalog` into a library, its content is read
+    /// from the blob store in `dir` like with `Library::load`
+    pub fn from_catalog(catalog: &Catalog, dir: &Path) -> Result<Self, Error> {
+        let mut lib = Self {
+            dir: Some(dir.to_owned()),
+            ..Default::default()
+        };
+        for entry in catalog.list(None)? {
+            match entry.visual {
+                true => {
+                    if let Some(b) = catalog.get(&entry.label)? {
+                        lib.manga.insert(entry.label, b);
+                    }
+                }
+                false => {
+                    if let Some(b) = catalog.get(&entry.label)? {
+                        lib.novels.insert(entry.label, b);
+                    }
+                }
+            }
+        }
+        Ok(lib)
+    }
+}
+
+#[test]
+fn catalog_books() {
+    use crate::Novel;
+    let mut cat = Catalog::memory().unwrap();
+    let page = Page::from("https://example.com/novel/chapter-1");
+    let mut book: Book<Novel> = Book {
+        title: Label("Some Novel".to_string()),
+        index: Page::from("https://example.com/novel"),
+        pos: 3,
+        ..Default::default()
+    };
+    book.chs.insert(0, Chapter {
+        id: 0,
+        src: Some(page.clone()),
+        ..Default::default()
+    });
+    let c = Content::from((page, Content::from("a".to_string())));
+    book.add_content(0, 0, c).unwrap();
+    cat.add(&book).unwrap();
+    cat.add(&book).unwrap();
+    let list = cat.list(Some(false)).unwrap();
+    assert_eq!((list.len(), list[0].chapters, list[0].pos), (1, 1, 3));
+    assert!(cat.list(Some(true)).unwrap().is_empty());
+    assert_eq!(cat.search("novel").unwrap().len(), 1);
+    assert!(cat.search("%").unwrap().is_empty());
+    cat.rename(&book.title, "Renamed".to_string()).unwrap();
+    let loaded: Book<Novel> = cat.get(&Label("Renamed".into())).unwrap().unwrap();
+    assert_eq!(loaded.chapter(0).len(), 1);
+    assert_eq!(loaded.index, book.index);
+    let dir = std::env::temp_dir().join("pagepal_catalog_books");
+    let lib: Library = Library::from_catalog(&cat, &dir).unwrap();
+    assert_eq!(lib.dir.as_deref(), Some(dir.as_path()));
+    assert_eq!(lib.novels.len(), 1);
+    let mut store = Store::open(&dir).unwrap();
+    cat.remove(&loaded.title, &mut store).unwrap();
+    assert!(cat.list(None).unwrap().is_empty());
+    std::fs::remove_dir_all(dir).ok();
+}
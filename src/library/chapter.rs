@@ -1,38 +1,49 @@
-use crate::{Content, Media, Page};
-use futures::future::join_all;
-use reqwest::Client;
+use crate::{Content, Error, Media, Num, Page, Retriever};
+use futures::{stream, StreamExt, TryStreamExt};
 use serde::{Deserialize as des, Serialize as ser};
 
 #[derive(Debug, Clone, Default, ser, des)]
 pub struct Chapter<T: Media> {
-    pub id:  u16,
-    pub src: Option<Page>,
+    pub id:     u16,
+    pub src:    Option<Page>,
+    /// Number the site gives the chapter, if it does. Kept since the site's
+    /// Finder isn't once the source is read back from the manifest.
+    #[serde(default)]
+    pub number: Option<Num>,
     #[serde(skip)]
-    pub cnt: Vec<Content<T>>,
+    pub cnt:    Vec<Content<T>>,
 }
 impl<T: Media> Chapter<T> {
     pub async fn set_cnt(
-        &mut self, content: Option<Vec<Content<T>>>,
-    ) -> &Vec<Content<T>> {
+        &mut self, content: Option<Vec<Content<T>>>, r: &Retriever,
+    ) -> Result<&Vec<Content<T>>, Error> {
         match content {
             Some(c) => self.cnt = c,
-            None => self.cnt = self.content(&Client::new()).await,
+            None => self.cnt = self.content(r).await?,
         }
-        &self.cnt
+        Ok(&self.cnt)
     }
 
-    pub async fn content(&self, client: &Client) -> Vec<Content<T>> {
-        let r = self.src.as_ref().unwrap().get_content::<T>().unwrap();
+    /// Downloads the content of the chapter within the retriever's limits
+    pub async fn content(&self, r: &Retriever) -> Result<Vec<Content<T>>, Error> {
+        let src = self.src.as_ref().ok_or_else(|| {
+            Error::Missing(format!("source of chapter {}", self.id))
+        })?;
+        let links = src
+            .get_content::<T>()
+            .ok_or_else(|| Error::Missing(format!("content on {}", src.loc)))?;
         if T::visual() {
-            join_all(r.iter().map(|s| Page::from(s)).map(|a| async move {
-                let p = Page::get_image(&a, client).await;
-                (a, Content::from(p)).into()
-            }))
+            stream::iter(links.iter().map(|s| Page::from(s)).map(
+                |a| async move {
+                    let p = r.image(&a).await?;
+                    Ok((a, Content::from(p)).into())
+                },
+            ))
+            .buffered(r.jobs())
+            .try_collect()
             .await
         } else {
-            vec![
-                (self.src.clone().unwrap(), Content::from(r.join("\n\n"))).into(),
-            ]
+            Ok(vec![(src.clone(), Content::from(links.join("\n\n"))).into()])
         }
     }
 }
@@ -1,9 +1,8 @@
-use crate::{Get, Page};
+use crate::{Error, Format, Get, Page, Store, Transcode};
 use serde::{Deserialize as des, Serialize as ser};
 use std::{
+    borrow::Cow,
     cmp::Ordering::{self, Equal, Greater, Less},
-    fs::File,
-    io::Write,
     path::PathBuf,
 };
 
@@ -21,7 +20,10 @@ where
 }
 
 impl Media for Novel {
-    fn from(src: Vec<u8>) -> Self { String::from_utf8(src).unwrap() }
+    fn from(src: Vec<u8>) -> Self {
+        String::from_utf8(src)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into())
+    }
 
     fn get(&self) -> &[u8] { self.as_bytes() }
 }
@@ -42,10 +44,25 @@ pub enum MediaData {
 pub struct Num(pub u16, pub Option<u8>);
 #[derive(Debug, Clone, Default, Eq, PartialEq, ser, des)]
 pub struct Content<T: Media> {
-    pub id:  u64,
-    pub src: Option<Page>,
+    pub id:     u64,
+    /// Chapter of the book the content belongs to
+    #[serde(default)]
+    pub ch:     Option<u16>,
+    pub src:    Option<Page>,
+    /// Image format of visual media, `None` for content saved before it was
+    /// detected, which was always saved as `jpg`
+    #[serde(default)]
+    pub format: Option<Format>,
+    /// Pages an image was cut into after the first one
+    #[serde(default)]
+    pub parts:  usize,
+    /// Blob of every page in the store, the first page's then the parts'
+    #[serde(default)]
+    pub hashes: Vec<String>,
+    #[serde(skip)]
+    data:       T,
     #[serde(skip)]
-    data:    T,
+    extra:      Vec<T>,
 }
 impl<T: Media> Content<T> {
     pub fn lighten(&self) {
@@ -55,50 +72,181 @@ impl<T: Media> Content<T> {
         }
     }
 
-    pub async fn fetch_image(&mut self) {
+    pub async fn fetch_image(&mut self) -> Result<(), Error> {
         if let (Some(page), data) = (&self.src, &mut self.data) {
             use reqwest::Client;
-            *data = T::from(page.get_image(&Client::new()).await);
+            let (bytes, mime) = page.get_image(&Client::new()).await?;
+            self.format = Format::sniff(&bytes).or(mime);
+            *data = T::from(bytes);
         }
+        Ok(())
     }
 
-    pub async fn fetch_novel(&mut self) {
+    pub async fn fetch_novel(&mut self) -> Result<(), Error> {
         if let (Some(page), data) = (&self.src, &mut self.data) {
             *data = T::from(
                 page.refresh(None)
-                    .await
-                    .unwrap()
+                    .await?
                     .text()
-                    .unwrap()
+                    .ok_or_else(|| {
+                        Error::Missing(format!("text on {}", page.loc))
+                    })?
                     .join("\n\n")
                     .bytes()
                     .collect(),
             );
         }
+        Ok(())
     }
 
-    pub async fn data_load(&mut self) {
+    pub async fn data_load(&mut self) -> Result<(), Error> {
+        if let Some(path) = self.local() {
+            self.data = T::from(tokio::fs::read(path).await?);
+            return Ok(());
+        }
         match T::visual() {
             true => self.fetch_image().await,
             false => self.fetch_novel().await,
-        };
+        }
     }
 
-    pub fn save(&self, pb: &PathBuf) {
-        std::fs::create_dir_all(&pb).unwrap();
+    /// File the content is saved to inside `pb`
+    pub fn path(&self, pb: &PathBuf) -> PathBuf {
         let p1 = format!("c{:04}", self.id / 256);
         let p2 = format!("p{:04}", self.id % 256);
         let mut pb = pb.join(p1 + &p2);
         if T::visual() {
-            pb.set_extension("jpg");
+            pb.set_extension(self.format.unwrap_or_default().ext());
+        }
+        pb
+    }
+
+    /// File page `n` of the content is saved to, the first page is at
+    /// `Content::path`
+    pub fn part_path(&self, pb: &PathBuf, n: usize) -> PathBuf {
+        let path = self.path(pb);
+        match (n, path.file_stem(), path.extension()) {
+            (0, ..) | (_, None, _) => path,
+            (n, Some(stem), ext) => {
+                let mut name = stem.to_owned();
+                name.push(format!("-{}", n));
+                let mut p = path.with_file_name(name);
+                if let Some(ext) = ext {
+                    p.set_extension(ext);
+                }
+                p
+            }
+        }
+    }
+
+    pub fn data(&self) -> &T { &self.data }
+
+    /// Re-encodes downloaded images, possibly cutting them into several pages
+    pub fn transcode(&mut self, t: &Transcode) -> Result<(), Error> {
+        if !T::visual() {
+            return Ok(());
         }
-        File::with_options()
-            .write(true)
-            .create(true)
-            .open(pb)
-            .unwrap()
-            .write(self.data.get())
-            .unwrap();
+        let (format, mut pages) = t.apply(self.data.get())?;
+        if pages.is_empty() {
+            return Err(Error::Parse("empty image".to_string()));
+        }
+        self.data = T::from(pages.remove(0));
+        self.extra = pages.into_iter().map(T::from).collect();
+        self.parts = self.extra.len();
+        self.format = Some(format);
+        Ok(())
+    }
+
+    /// Every page of the content, the ones that aren't loaded are read from
+    /// `store`
+    pub fn pages(
+        &self, pb: &PathBuf, store: &Store,
+    ) -> Result<Vec<Cow<[u8]>>, Error> {
+        let mut pages = vec![self.bytes(pb, store)?];
+        for n in 1..=self.parts {
+            pages.push(match self.extra.get(n - 1) {
+                Some(a) => Cow::Borrowed(a.get()),
+                None => Cow::Owned(self.read(pb, store, n)?),
+            });
+        }
+        Ok(pages)
+    }
+
+    /// Reads in the file the content was imported from, if it isn't loaded
+    pub fn load_local(&mut self) -> Result<(), Error> {
+        if let (true, Some(path)) = (self.data.get().is_empty(), self.local()) {
+            self.data = T::from(std::fs::read(path)?);
+        }
+        Ok(())
+    }
+
+    /// Drops the loaded pages if they're in the blob store, they're read
+    /// from there again when needed
+    pub fn unload(&mut self) {
+        if !self.hashes.is_empty() {
+            self.data = T::from(vec![]);
+            self.extra.clear();
+        }
+    }
+
+    /// File the content was imported from, if it's a local file and not an
+    /// entry of an archive
+    pub fn local(&self) -> Option<PathBuf> {
+        let loc = &self.src.as_ref()?.loc;
+        match (loc.scheme(), loc.fragment()) {
+            ("file", None) => loc.to_file_path().ok(),
+            _ => None,
+        }
+    }
+
+    /// The data if it's loaded, or what was stored or imported otherwise
+    pub fn bytes(&self, pb: &PathBuf, store: &Store) -> Result<Cow<[u8]>, Error> {
+        match (self.data.get(), self.hashes.is_empty(), self.local()) {
+            ([], true, Some(path)) => Ok(Cow::Owned(std::fs::read(path)?)),
+            ([], ..) => Ok(Cow::Owned(self.read(pb, store, 0)?)),
+            (data, ..) => Ok(Cow::Borrowed(data)),
+        }
+    }
+
+    /// Reads page `n` from `store`, or from the book directory `pb` if it
+    /// was saved before content was stored by hash
+    fn read(
+        &self, pb: &PathBuf, store: &Store, n: usize,
+    ) -> Result<Vec<u8>, Error> {
+        match self.hashes.get(n) {
+            Some(hash) => store.get(hash),
+            None => Ok(std::fs::read(self.part_path(pb, n))?),
+        }
+    }
+
+    /// Puts the loaded pages into the blob store and releases the blobs they
+    /// replace. Content that isn't loaded is left alone.
+    pub fn store(&mut self, store: &mut Store) -> Result<(), Error> {
+        if self.data.get().is_empty() {
+            return Ok(());
+        }
+        let pages = std::iter::once(&self.data)
+            .chain(&self.extra)
+            .map(|a| a.get())
+            .collect::<Vec<_>>();
+        let hashes = pages.iter().map(|a| Store::hash(a)).collect::<Vec<_>>();
+        if hashes == self.hashes {
+            return Ok(());
+        }
+        for page in pages {
+            store.put(page)?;
+        }
+        for old in std::mem::replace(&mut self.hashes, hashes) {
+            store.release(&old)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the content's references to its blobs
+    pub fn release(&self, store: &mut Store) -> Result<(), Error> {
+        self.hashes
+            .iter()
+            .try_for_each(|a| store.release(a).map(|_| ()))
     }
 }
 
@@ -192,11 +340,22 @@ impl<T: Media> From<&String> for Content<T> {
 impl<T: Media> From<Vec<u8>> for Content<T> {
     fn from(data: Vec<u8>) -> Self {
         Content::<T> {
+            format: Format::sniff(&data).filter(|_| T::visual()),
             data: T::from(data),
             ..Default::default()
         }
     }
 }
+/// Downloaded bytes and the format the server claimed they are in
+impl<T: Media> From<(Vec<u8>, Option<Format>)> for Content<T> {
+    fn from(tup: (Vec<u8>, Option<Format>)) -> Self {
+        let mut a = Content::from(tup.0);
+        if T::visual() {
+            a.format = a.format.or(tup.1);
+        }
+        a
+    }
+}
 impl<T: Media> From<(Page, Content<T>)> for Content<T> {
     fn from(tup: (Page, Content<T>)) -> Self {
         let mut a = tup.1;
@@ -0,0 +1,98 @@
+use serde::{Deserialize as des, Serialize as ser};
+
+/// Encoding of an image, as told by its magic numbers or Content-Type
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ser, des)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Avif,
+    Bmp,
+}
+impl Format {
+    /// Recognizes the format from the first bytes of the data
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data {
+            [0xff, 0xd8, 0xff, ..] => Some(Format::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, ..] => {
+                Some(Format::Png)
+            }
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Format::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+                Some(Format::WebP)
+            }
+            [_, _, _, _, b'f', b't', b'y', b'p', b'a', b'v', b'i', b'f' | b's', ..] => {
+                Some(Format::Avif)
+            }
+            [b'B', b'M', ..] => Some(Format::Bmp),
+            _ => None,
+        }
+    }
+
+    /// Reads a Content-Type like `image/png; charset=binary`
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime.split(';').next()?.trim().to_ascii_lowercase().as_str() {
+            "image/jpeg" | "image/jpg" | "image/pjpeg" => Some(Format::Jpeg),
+            "image/png" => Some(Format::Png),
+            "image/gif" => Some(Format::Gif),
+            "image/webp" => Some(Format::WebP),
+            "image/avif" => Some(Format::Avif),
+            "image/bmp" | "image/x-ms-bmp" => Some(Format::Bmp),
+            _ => None,
+        }
+    }
+
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "png" => Some(Format::Png),
+            "gif" => Some(Format::Gif),
+            "webp" => Some(Format::WebP),
+            "avif" => Some(Format::Avif),
+            "bmp" => Some(Format::Bmp),
+            _ => None,
+        }
+    }
+
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpg",
+            Format::Png => "png",
+            Format::Gif => "gif",
+            Format::WebP => "webp",
+            Format::Avif => "avif",
+            Format::Bmp => "bmp",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Format::Jpeg => "image/jpeg",
+            Format::Png => "image/png",
+            Format::Gif => "image/gif",
+            Format::WebP => "image/webp",
+            Format::Avif => "image/avif",
+            Format::Bmp => "image/bmp",
+        }
+    }
+}
+impl Default for Format {
+    fn default() -> Self { Format::Jpeg }
+}
+
+#[test]
+fn format_sniffing() {
+    assert_eq!(Format::sniff(&[0xff, 0xd8, 0xff, 0xe0]), Some(Format::Jpeg));
+    assert_eq!(Format::sniff(b"\x89PNG\r\n\x1a\n...."), Some(Format::Png));
+    assert_eq!(Format::sniff(b"GIF89a.."), Some(Format::Gif));
+    assert_eq!(Format::sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(Format::WebP));
+    assert_eq!(Format::sniff(b"\0\0\0\x1cftypavif"), Some(Format::Avif));
+    assert_eq!(Format::sniff(b"<html>"), None);
+    assert_eq!(
+        Format::from_mime("image/webp; charset=binary"),
+        Some(Format::WebP)
+    );
+    assert_eq!(Format::from_ext("JPEG").map(|a| a.ext()), Some("jpg"));
+}
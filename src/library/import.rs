@@ -0,0 +1,318 @@
+use crate::{Book, Chapter, Content, Error, Format, Label, Media, Num, Page};
+use select::{
+    document::Document,
+    predicate::{Descendant, Name},
+};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+use url::Url;
+use zip::ZipArchive;
+
+static IMAGES: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp", "avif"];
+static TEXTS: &[&str] = &["txt", "md", "html", "htm", "xhtml"];
+
+/// A chapter found on disk, its content in reading order
+struct Part<T: Media> {
+    src:   Url,
+    items: Vec<Content<T>>,
+}
+
+fn ext(path: &Path) -> String {
+    path.extension()
+        .and_then(|a| a.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn file_url(path: &Path) -> Result<Url, Error> {
+    Url::from_file_path(fs::canonicalize(path)?)
+        .map_err(|_| Error::Parse(format!("path {:?}", path)))
+}
+
+/// Last number in a name, with the same float chapters as `Num`, so
+/// `Chapter 12.5` comes between `Chapter 12` and `Chapter 13`
+pub fn number(name: &str) -> Option<Num> {
+    name.split(|a: char| !a.is_ascii_digit() && a != '.')
+        .map(|a| a.trim_matches('.'))
+        .filter(|a| !a.is_empty())
+        .last()?
+        .parse::<f32>()
+        .ok()
+        .map(Num::from)
+}
+
+/// Entries of `dir` ordered by the numbers in their names
+fn sorted(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|a| Ok::<_, Error>(a?.path()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    paths.sort_by_cached_key(|a| {
+        let name = match a.is_dir() {
+            true => a.file_name(),
+            false => a.file_stem(),
+        };
+        let name = name.and_then(|a| a.to_str()).unwrap_or_default();
+        (number(name), name.to_string())
+    });
+    Ok(paths)
+}
+
+/// Whether the folder or file holds images rather than text, going by what
+/// most of the files are
+pub fn is_visual(path: &Path) -> Result<bool, Error> {
+    fn count(path: &Path) -> Result<(usize, usize), Error> {
+        if path.is_dir() {
+            return fs::read_dir(path)?.try_fold((0, 0), |acc, a| {
+                let (i, t) = count(&a?.path())?;
+                Ok::<_, Error>((acc.0 + i, acc.1 + t))
+            });
+        }
+        let e = ext(path);
+        Ok(match e.as_str() {
+            "cbz" => (1, 0),
+            "epub" => (0, 1),
+            _ if IMAGES.contains(&e.as_str()) => (1, 0),
+            _ if TEXTS.contains(&e.as_str()) => (0, 1),
+            _ => (0, 0),
+        })
+    }
+    let (images, texts) = count(path)?;
+    Ok(images > texts)
+}
+
+/// Paragraphs of an html document, or all of its text if it has none
+fn html_text(html: &str) -> String {
+    let doc = Document::from(html);
+    let paras = doc
+        .select(Name("p"))
+        .map(|a| a.text().trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<_>>();
+    match paras.is_empty() {
+        true => doc
+            .select(Name("body"))
+            .next()
+            .map_or_else(String::new, |a| a.text().trim().to_string()),
+        false => paras.join("\n\n"),
+    }
+}
+
+fn read_entry(zip: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![];
+    zip.by_name(name)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// The images of a comic archive as a single chapter
+fn cbz<T: Media>(path: &Path) -> Result<Vec<Part<T>>, Error> {
+    let src = file_url(path)?;
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let mut names = zip
+        .file_names()
+        .filter(|a| IMAGES.contains(&ext(Path::new(a)).as_str()))
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>();
+    names.sort_by_cached_key(|a| {
+        (
+            number(a.rsplit_once('.').map_or(a.as_str(), |a| a.0)),
+            a.clone(),
+        )
+    });
+    let items = names
+        .iter()
+        .map(|name| {
+            let mut c = Content::from(read_entry(&mut zip, name)?);
+            let mut loc = src.clone();
+            loc.set_fragment(Some(name));
+            c.src = Some(Page::from(loc.as_str()));
+            Ok::<_, Error>(c)
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(vec![Part { src, items }])
+}
+
+/// A chapter per document in the reading order of an EPUB
+fn epub<T: Media>(path: &Path) -> Result<Vec<Part<T>>, Error> {
+    let src = file_url(path)?;
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let container = read_entry(&mut zip, "META-INF/container.xml")?;
+    let opf = Document::from(String::from_utf8_lossy(&container).as_ref())
+        .select(Name("rootfile"))
+        .find_map(|a| a.attr("full-path").map(|a| a.to_string()))
+        .ok_or_else(|| Error::Missing(format!("rootfile in {:?}", path)))?;
+    let base = opf.rsplit_once('/').map_or("", |a| a.0).to_string();
+    let doc = Document::from(
+        String::from_utf8_lossy(&read_entry(&mut zip, &opf)?).as_ref(),
+    );
+    let hrefs = doc
+        .select(Descendant(Name("spine"), Name("itemref")))
+        .filter_map(|a| a.attr("idref"))
+        .filter_map(|id| {
+            doc.select(Name("item"))
+                .find(|a| a.attr("id") == Some(id))?
+                .attr("href")
+                .map(|a| match base.is_empty() {
+                    true => a.to_string(),
+                    false => format!("{}/{}", base, a),
+                })
+        })
+        .collect::<Vec<_>>();
+    let mut parts = vec![];
+    for href in hrefs {
+        let text =
+            html_text(&String::from_utf8_lossy(&read_entry(&mut zip, &href)?));
+        if text.is_empty() {
+            continue;
+        }
+        let mut loc = src.clone();
+        loc.set_fragment(Some(&href));
+        let mut c = Content::from(text);
+        c.src = Some(Page::from(loc.as_str()));
+        parts.push(Part {
+            src:   loc,
+            items: vec![c],
+        });
+    }
+    Ok(parts)
+}
+
+/// A local file as content, loading what has to be converted to text
+fn file<T: Media>(path: &Path) -> Result<Content<T>, Error> {
+    let mut c = match ext(path).as_str() {
+        "html" | "htm" | "xhtml" => {
+            Content::from(html_text(&fs::read_to_string(path)?))
+        }
+        _ => Content::default(),
+    };
+    if T::visual() {
+        c.format = Format::from_ext(&ext(path));
+    }
+    c.src = Some(Page::from(file_url(path)?.as_str()));
+    Ok(c)
+}
+
+fn wanted<T: Media>(path: &Path) -> bool {
+    match T::visual() {
+        true => IMAGES.contains(&ext(path).as_str()),
+        false => TEXTS.contains(&ext(path).as_str()),
+    }
+}
+
+/// Chapters under `path`: a chapter per sub-folder and archive, the loose
+/// images of a folder as one chapter and every loose text file as its own
+fn parts<T: Media>(path: &Path) -> Result<Vec<Part<T>>, Error> {
+    match (path.is_dir(), ext(path).as_str()) {
+        (false, "cbz") => return cbz(path),
+        (false, "epub") => return epub(path),
+        (false, _) => {
+            return Ok(vec![Part {
+                src:   file_url(path)?,
+                items: vec![file(path)?],
+            }])
+        }
+        _ => {}
+    }
+    let mut parts = vec![];
+    let mut loose = vec![];
+    for entry in sorted(path)? {
+        match (entry.is_dir(), ext(&entry).as_str()) {
+            (true, _) => parts.push(Part {
+                src:   file_url(&entry)?,
+                items: sorted(&entry)?
+                    .iter()
+                    .filter(|a| a.is_file() && wanted::<T>(a))
+                    .map(|a| file(a))
+                    .collect::<Result<_, _>>()?,
+            }),
+            (false, "cbz") if T::visual() => parts.extend(cbz(&entry)?),
+            (false, "epub") if !T::visual() => parts.extend(epub(&entry)?),
+            (false, _) if wanted::<T>(&entry) => match T::visual() {
+                true => loose.push(file(&entry)?),
+                false => parts.push(Part {
+                    src:   file_url(&entry)?,
+                    items: vec![file(&entry)?],
+                }),
+            },
+            _ => {}
+        }
+    }
+    if !loose.is_empty() {
+        parts.insert(0, Part {
+            src:   file_url(path)?,
+            items: loose,
+        });
+    }
+    Ok(parts.into_iter().filter(|a| !a.items.is_empty()).collect())
+}
+
+/// Builds a book out of a local folder, archive or file, named after it.
+/// Loose files aren't read yet, archived content is loaded. See
+/// `Library::import` for putting it in the library.
+pub fn book<T: Media>(path: &Path) -> Result<Book<T>, Error> {
+    let title = path
+        .file_stem()
+        .and_then(|a| a.to_str())
+        .ok_or_else(|| Error::Parse(format!("name of {:?}", path)))?;
+    let mut bk = Book {
+        title: Label(title.to_string()),
+        index: Page::from(file_url(path)?.as_str()),
+        ..Default::default()
+    };
+    let mut seq = 0;
+    for (id, part) in parts::<T>(path)?.into_iter().enumerate() {
+        let id = id as u16;
+        for (page, mut c) in part.items.into_iter().enumerate() {
+            c.id = id as u64 * 256 + page as u64;
+            c.ch = Some(id);
+            bk.content.insert(Num(seq, None), c);
+            seq += 1;
+        }
+        bk.chs.insert(id, Chapter {
+            id,
+            src: Some(Page::from(part.src.as_str())),
+            ..Default::default()
+        });
+    }
+    match bk.content.is_empty() {
+        true => Err(Error::Missing(format!("anything to import in {:?}", path))),
+        false => Ok(bk),
+    }
+}
+
+#[test]
+fn import_folder() {
+    use crate::Manga;
+    let dir = std::env::temp_dir().join("pagepal_import_folder");
+    for (ch, page) in &[
+        ("Chapter 10", "1"),
+        ("Chapter 2", "1"),
+        ("Chapter 1.5", "10"),
+        ("Chapter 1.5", "2"),
+    ] {
+        fs::create_dir_all(dir.join(ch)).unwrap();
+        fs::write(dir.join(ch).join(format!("{}.jpg", page)), page).unwrap();
+    }
+    assert!(is_visual(&dir).unwrap());
+    let book: Book<Manga> = book(&dir).unwrap();
+    let order = book
+        .content
+        .values()
+        .filter_map(|a| a.local())
+        .map(|a| {
+            a.strip_prefix(fs::canonicalize(&dir).unwrap())
+                .unwrap()
+                .to_owned()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(order, vec![
+        PathBuf::from("Chapter 1.5/2.jpg"),
+        PathBuf::from("Chapter 1.5/10.jpg"),
+        PathBuf::from("Chapter 2/1.jpg"),
+        PathBuf::from("Chapter 10/1.jpg"),
+    ]);
+    assert_eq!(book.chapter(1).len(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,163 @@
+use crate::{Label, Meta};
+use serde::{Deserialize as des, Serialize as ser};
+use url::Url;
+
+/// Whether a book is still being published
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ser, des)]
+pub enum Publication {
+    Unknown,
+    Ongoing,
+    Completed,
+    Hiatus,
+    Cancelled,
+}
+impl Default for Publication {
+    fn default() -> Self { Publication::Unknown }
+}
+impl From<&str> for Publication {
+    fn from(s: &str) -> Self {
+        let s = s.to_lowercase();
+        match () {
+            _ if s.contains("ongoing") => Publication::Ongoing,
+            _ if s.contains("complete") => Publication::Completed,
+            _ if s.contains("hiatus") => Publication::Hiatus,
+            _ if s.contains("cancel") || s.contains("dropped") => {
+                Publication::Cancelled
+            }
+            _ => Publication::Unknown,
+        }
+    }
+}
+
+/// What is known about a book besides its content
+#[derive(Clone, Debug, Default, PartialEq, ser, des)]
+#[serde(default)]
+pub struct Metadata {
+    pub alt_titles:  Vec<String>,
+    pub authors:     Vec<String>,
+    pub artists:     Vec<String>,
+    pub description: Option<String>,
+    /// Genres and tags
+    pub genres:      Vec<String>,
+    pub status:      Publication,
+    pub language:    Option<String>,
+    pub cover:       Option<Url>,
+    pub source:      Option<Url>,
+    pub rating:      Option<f32>,
+}
+
+fn list(s: &str, seps: &[char]) -> Vec<String> {
+    s.split(seps)
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| a.to_string())
+        .collect()
+}
+
+impl Metadata {
+    /// Reads the keys `Finder::meta_def` extracts: `alt_titles`, `author`,
+    /// `artist`, `description`, `genres`, `status`, `language`, `cover`,
+    /// `rating` and `url`, falling back to their OpenGraph counterparts.
+    /// Relative urls are resolved against `base`, the page they came from.
+    pub fn from_meta(meta: &Meta, base: &Url) -> Self {
+        let get = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|k| meta.get(*k))
+                .map(|a| a.trim())
+                .filter(|a| !a.is_empty())
+        };
+        let url = |keys: &[&str]| get(keys).and_then(|a| base.join(a).ok());
+        Self {
+            alt_titles:  get(&["alt_titles"])
+                .map_or_else(Vec::new, |a| list(a, &[';', '\n'])),
+            authors:     get(&["author", "book:author"])
+                .map_or_else(Vec::new, |a| list(a, &[',', ';', '\n'])),
+            artists:     get(&["artist"])
+                .map_or_else(Vec::new, |a| list(a, &[',', ';', '\n'])),
+            description: get(&["description", "og:description"])
+                .map(|a| a.to_string()),
+            genres:      get(&["genres", "tags", "book:tag"])
+                .map_or_else(Vec::new, |a| list(a, &[',', ';', '\n'])),
+            status:      get(&["status"])
+                .map_or_else(Default::default, Publication::from),
+            language:    get(&["language", "og:locale"]).map(|a| a.to_string()),
+            cover:       url(&["cover", "og:image"]),
+            source:      url(&["url", "og:url"])
+                .or_else(|| Some(base.to_owned())),
+            rating:      get(&["rating"]).and_then(|a| {
+                a.split(|c: char| !c.is_ascii_digit() && c != '.')
+                    .find_map(|a| a.parse().ok())
+            }),
+        }
+    }
+
+    /// Whether the book with this metadata and `title` passes the filter
+    pub fn matches(&self, title: &Label, f: &Filter) -> bool {
+        let has = |hay: &str, needle: &str| {
+            hay.to_lowercase().contains(&needle.to_lowercase())
+        };
+        f.title.as_ref().map_or(true, |t| {
+            has(&title.0, t) || self.alt_titles.iter().any(|a| has(a, t))
+        }) && f.author.as_ref().map_or(true, |t| {
+            self.authors.iter().chain(&self.artists).any(|a| has(a, t))
+        }) && f
+            .genres
+            .iter()
+            .all(|g| self.genres.iter().any(|a| a.eq_ignore_ascii_case(g))) &&
+            f.status.map_or(true, |s| s == self.status) &&
+            f.language.as_ref().map_or(true, |l| {
+                self.language.as_ref().map_or(false, |a| has(a, l))
+            }) &&
+            f.min_rating
+                .map_or(true, |r| self.rating.map_or(false, |a| a >= r))
+    }
+}
+
+/// Conditions on the metadata of books, unset ones match everything
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    /// Part of the title or of an alternative title
+    pub title:      Option<String>,
+    /// Part of the name of an author or artist
+    pub author:     Option<String>,
+    /// Genres the book has to have all of
+    pub genres:     Vec<String>,
+    pub status:     Option<Publication>,
+    pub language:   Option<String>,
+    pub min_rating: Option<f32>,
+}
+
+#[test]
+fn metadata_filter() {
+    let base = "https://example.com/manga/test".parse().unwrap();
+    let meta = vec![
+        ("alt_titles", "Other; Autre"),
+        ("author", "A. Writer, B. Artist"),
+        ("genres", "Action, Drama"),
+        ("status", "Status: Ongoing"),
+        ("og:image", "/covers/test.jpg"),
+        ("rating", "4.5 / 5"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect::<Meta>();
+    let m = Metadata::from_meta(&meta, &base);
+    assert_eq!(m.alt_titles, vec!["Other", "Autre"]);
+    assert_eq!(m.status, Publication::Ongoing);
+    assert_eq!(
+        m.cover.as_ref().map(Url::as_str),
+        Some("https://example.com/covers/test.jpg")
+    );
+    assert_eq!(m.rating, Some(4.5));
+    let title = Label("Test".to_string());
+    assert!(m.matches(&title, &Filter {
+        title: Some("autre".into()),
+        genres: vec!["drama".into()],
+        min_rating: Some(4.),
+        ..Default::default()
+    }));
+    assert!(!m.matches(&title, &Filter {
+        status: Some(Publication::Completed),
+        ..Default::default()
+    }));
+}
@@ -0,0 +1,357 @@
+use crate::{Book, Content, Error, Label, Media, Num, Store};
+use serde::{Deserialize as des, Serialize as ser};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::ErrorKind::NotFound,
+    iter::once,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// BM25 parameters
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// A piece of content that matched a query
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub book:    Label,
+    pub num:     Num,
+    pub ch:      Option<u16>,
+    pub score:   f32,
+    /// Byte offset of the first match in the text
+    pub offset:  usize,
+    pub snippet: String,
+}
+
+#[derive(Clone, Debug, ser, des)]
+struct Doc {
+    book: Label,
+    num:  Num,
+    ch:   Option<u16>,
+    hash: String,
+    len:  u32,
+}
+
+/// Lowercased words of the text with their byte offsets
+pub fn tokens(text: &str) -> Vec<(String, u32)> {
+    let mut out = vec![];
+    let mut start = None;
+    for (i, a) in text.char_indices().chain(once((text.len(), ' '))) {
+        match (a.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                out.push((text[s..i].to_lowercase(), s as u32));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Words and `"quoted phrases"` of a query, a word being a phrase of one
+fn parse(query: &str) -> Vec<Vec<String>> {
+    query
+        .split('"')
+        .enumerate()
+        .flat_map(|(i, part)| {
+            let words = tokens(part).into_iter().map(|a| a.0);
+            match i % 2 {
+                1 => vec![words.collect::<Vec<_>>()],
+                _ => words.map(|a| vec![a]).collect(),
+            }
+        })
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+/// About `width` bytes of text around `offset` on one line, without cutting
+/// words
+pub fn snippet(text: &str, offset: usize, width: usize) -> String {
+    let mut start = offset.min(text.len()).saturating_sub(width / 2);
+    let mut end = (offset + width / 2).min(text.len());
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    start = text[..start]
+        .char_indices()
+        .rev()
+        .find(|a| a.1.is_whitespace())
+        .map_or(0, |(i, a)| i + a.len_utf8());
+    end = text[end..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |a| end + a);
+    format!(
+        "{}{}{}",
+        if start > 0 { "…" } else { "" },
+        text[start..end]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+        if end < text.len() { "…" } else { "" }
+    )
+}
+
+/// Inverted index over the text of novels, with word positions for phrase
+/// queries and BM25 ranking
+#[derive(Clone, Debug, Default, ser, des)]
+pub struct Index {
+    #[serde(skip)]
+    path:    PathBuf,
+    /// Whether it changed since it was last written, and when that was
+    #[serde(skip)]
+    changed: bool,
+    #[serde(skip)]
+    flushed: Option<Instant>,
+    next:    u32,
+    docs:    BTreeMap<u32, Doc>,
+    /// Word to the documents it's in, with its positions and byte offsets
+    terms:   HashMap<String, BTreeMap<u32, Vec<(u32, u32)>>>,
+}
+impl Index {
+    /// The index file of the library with its manifest in `dir`. It can be
+    /// rebuilt from the stored text with `Library::save`.
+    pub fn root(dir: &Path) -> PathBuf { dir.join("search.json") }
+
+    /// Opens the index at `path`, or an empty one if there is none yet
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut i: Self = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == NotFound => Default::default(),
+            Err(e) => return Err(e.into()),
+        };
+        i.path = path.to_owned();
+        Ok(i)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(tmp, &self.path)?;
+        self.changed = false;
+        self.flushed = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Flushes the changes if it wasn't flushed in the last `every`, so
+    /// content filed one at a time isn't written out one at a time
+    pub fn sync(&mut self, every: Duration) -> Result<(), Error> {
+        match self.flushed {
+            _ if !self.changed => Ok(()),
+            Some(t) if t.elapsed() < every => Ok(()),
+            _ => self.flush(),
+        }
+    }
+
+    /// Whether there are changes that weren't flushed yet
+    pub fn changed(&self) -> bool { self.changed }
+
+    /// Indexes the text of a content, replacing what was indexed for it
+    /// before. Returns false if the text didn't change.
+    pub fn add(
+        &mut self, book: &Label, num: &Num, ch: Option<u16>, text: &str,
+    ) -> bool {
+        let hash = Store::hash(text.as_bytes());
+        let old = self
+            .docs
+            .iter()
+            .find(|(_, d)| &d.book == book && &d.num == num)
+            .map(|(id, d)| (*id, d.hash == hash));
+        match old {
+            Some((_, true)) => return false,
+            Some((id, false)) => self.remove(|a| *a == id),
+            None => {}
+        }
+        let id = self.next;
+        self.next += 1;
+        let words = tokens(text);
+        for (pos, (word, offset)) in words.iter().enumerate() {
+            self.terms
+                .entry(word.to_owned())
+                .or_default()
+                .entry(id)
+                .or_default()
+                .push((pos as u32, *offset));
+        }
+        self.docs.insert(id, Doc {
+            book: book.to_owned(),
+            num: num.to_owned(),
+            ch,
+            hash,
+            len: words.len() as u32,
+        });
+        self.changed = true;
+        true
+    }
+
+    /// Indexes the text of a content of `book` if it's loaded, returning
+    /// whether it changed
+    pub fn add_content<T: Media>(
+        &mut self, book: &Label, num: &Num, c: &Content<T>,
+    ) -> bool {
+        match c.data().get() {
+            [] => false,
+            data => {
+                let text = String::from_utf8_lossy(data);
+                self.add(book, num, c.ch, &text)
+            }
+        }
+    }
+
+    /// Indexes the loaded content of a book, returning how much of it changed
+    pub fn add_book<T: Media>(&mut self, book: &Book<T>) -> usize {
+        book.content
+            .iter()
+            .filter(|(num, c)| self.add_content(&book.title, num, c))
+            .count()
+    }
+
+    fn remove(&mut self, pred: impl Fn(&u32) -> bool) {
+        self.changed = true;
+        self.docs.retain(|id, _| !pred(id));
+        self.terms.retain(|_, docs| {
+            docs.retain(|id, _| !pred(id));
+            !docs.is_empty()
+        });
+    }
+
+    pub fn remove_book(&mut self, book: &Label) {
+        let ids = self
+            .docs
+            .iter()
+            .filter(|(_, d)| &d.book == book)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        self.remove(|id| ids.contains(id));
+    }
+
+    pub fn rename_book(&mut self, book: &Label, name: &Label) {
+        self.docs
+            .values_mut()
+            .filter(|d| &d.book == book)
+            .for_each(|d| d.book = name.to_owned());
+        self.changed = true;
+    }
+
+    /// Byte offsets where the phrase starts, per document
+    fn matches(&self, phrase: &[String]) -> BTreeMap<u32, Vec<u32>> {
+        let first = match phrase.first().and_then(|a| self.terms.get(a)) {
+            Some(a) => a,
+            None => return BTreeMap::new(),
+        };
+        first
+            .iter()
+            .filter_map(|(doc, occ)| {
+                let offsets = occ
+                    .iter()
+                    .filter(|(pos, _)| {
+                        phrase[1..].iter().enumerate().all(|(i, word)| {
+                            self.terms.get(word).and_then(|a| a.get(doc)).map_or(
+                                false,
+                                |a| {
+                                    a.binary_search_by_key(
+                                        &(pos + i as u32 + 1),
+                                        |a| a.0,
+                                    )
+                                    .is_ok()
+                                },
+                            )
+                        })
+                    })
+                    .map(|a| a.1)
+                    .collect::<Vec<_>>();
+                match offsets.is_empty() {
+                    true => None,
+                    false => Some((*doc, offsets)),
+                }
+            })
+            .collect()
+    }
+
+    /// Documents containing every word and phrase of the query, best first.
+    /// The snippets are left empty.
+    pub fn search(&self, query: &str) -> Vec<Hit> {
+        let phrases = parse(query);
+        if phrases.is_empty() || self.docs.is_empty() {
+            return vec![];
+        }
+        let n = self.docs.len() as f32;
+        let avg = self.docs.values().map(|a| a.len as f32).sum::<f32>() / n;
+        let mut found: Option<BTreeMap<u32, (f32, u32)>> = None;
+        for phrase in phrases {
+            let matches = self.matches(&phrase);
+            let df = matches.len() as f32;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.).ln();
+            let scored = matches
+                .into_iter()
+                .map(|(doc, offsets)| {
+                    let tf = offsets.len() as f32;
+                    let len = self.docs[&doc].len as f32;
+                    let score = idf * tf * (K1 + 1.) /
+                        (tf + K1 * (1. - B + B * len / avg));
+                    (doc, (score, offsets[0]))
+                })
+                .collect::<BTreeMap<_, _>>();
+            found = Some(match found {
+                None => scored,
+                Some(prev) => prev
+                    .into_iter()
+                    .filter_map(|(doc, (score, offset))| {
+                        let (s, o) = scored.get(&doc)?;
+                        Some((doc, (score + s, offset.min(*o))))
+                    })
+                    .collect(),
+            });
+        }
+        let mut hits = found
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, (score, offset))| {
+                let doc = &self.docs[&id];
+                Hit {
+                    book: doc.book.to_owned(),
+                    num: doc.num.to_owned(),
+                    ch: doc.ch,
+                    score,
+                    offset: offset as usize,
+                    snippet: String::new(),
+                }
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        hits
+    }
+}
+
+#[test]
+fn text_search() {
+    let mut index = Index::default();
+    let book = Label("Book".to_string());
+    let one = "The quick brown fox jumps over the lazy dog.";
+    let two = "A brown dog, and a quick fox. The fox, quick again.";
+    assert!(index.add(&book, &Num(1, None), Some(0), one));
+    assert!(index.add(&book, &Num(2, None), Some(1), two));
+    assert!(!index.add(&book, &Num(2, None), Some(1), two));
+    assert!(index.changed());
+    let hits = index.search("quick fox");
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].num, Num(2, None));
+    let hits = index.search("\"quick brown\"");
+    assert_eq!(hits.len(), 1);
+    assert_eq!(snippet(one, hits[0].offset, 20), "The quick brown…");
+    assert!(index.search("\"brown quick\"").is_empty());
+    index.remove_book(&book);
+    assert!(index.search("fox").is_empty() && index.terms.is_empty());
+}
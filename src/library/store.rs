@@ -0,0 +1,129 @@
+use crate::Error;
+use serde::{Deserialize as des, Serialize as ser};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind::NotFound,
+    path::{Path, PathBuf},
+};
+
+static REFS: &str = "refs.json";
+
+/// Content-addressed blobs, stored once per distinct content under the hex
+/// SHA-256 of their bytes and deleted when nothing refers to them anymore
+#[derive(Clone, Debug, Default, ser, des)]
+pub struct Store {
+    #[serde(skip)]
+    dir:  PathBuf,
+    refs: BTreeMap<String, u32>,
+}
+impl Store {
+    /// Where the library with its manifest in `dir` keeps its blobs
+    pub fn root(dir: &Path) -> PathBuf { dir.join("blobs") }
+
+    fn locate(dir: &Path, hash: &str) -> PathBuf {
+        dir.join(hash.get(..2).unwrap_or("00")).join(hash)
+    }
+
+    pub fn hash(data: &[u8]) -> String { format!("{:x}", Sha256::digest(data)) }
+
+    /// Opens the store in `dir`, or an empty one if there is none yet
+    pub fn open(dir: &Path) -> Result<Self, Error> {
+        let mut s: Self = match fs::read(dir.join(REFS)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == NotFound => Default::default(),
+            Err(e) => return Err(e.into()),
+        };
+        s.dir = dir.to_owned();
+        Ok(s)
+    }
+
+    pub fn path(&self, hash: &str) -> PathBuf { Self::locate(&self.dir, hash) }
+
+    /// How many contents refer to the blob
+    pub fn refs(&self, hash: &str) -> u32 {
+        self.refs.get(hash).copied().unwrap_or_default()
+    }
+
+    /// Adds a reference to `data`, writing it only if it isn't stored yet
+    pub fn put(&mut self, data: &[u8]) -> Result<String, Error> {
+        let hash = Self::hash(data);
+        let path = self.path(&hash);
+        if !path.exists() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, data)?;
+            fs::rename(tmp, &path)?;
+        }
+        *self.refs.entry(hash.to_owned()).or_default() += 1;
+        Ok(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        Ok(fs::read(self.path(hash))?)
+    }
+
+    /// Drops a reference, deleting the blob with the last one. Returns
+    /// whether it was deleted.
+    pub fn release(&mut self, hash: &str) -> Result<bool, Error> {
+        let count = match self.refs.get_mut(hash) {
+            Some(c) => {
+                *c = c.saturating_sub(1);
+                *c
+            }
+            None => return Ok(false),
+        };
+        if count > 0 {
+            return Ok(false);
+        }
+        self.refs.remove(hash);
+        match fs::remove_file(self.path(hash)) {
+            Err(e) if e.kind() != NotFound => Err(e.into()),
+            _ => Ok(true),
+        }
+    }
+
+    /// Rehashes every referenced blob, returning the ones that are missing or
+    /// don't match their hash anymore
+    pub fn verify(&self) -> Result<Vec<String>, Error> {
+        let mut bad = vec![];
+        for hash in self.refs.keys() {
+            match fs::read(self.path(hash)) {
+                Ok(data) if &Self::hash(&data) == hash => {}
+                Ok(_) => bad.push(hash.to_owned()),
+                Err(e) if e.kind() == NotFound => bad.push(hash.to_owned()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(bad)
+    }
+
+    /// Writes the reference counts back to the store
+    pub fn flush(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(REFS);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        Ok(fs::rename(tmp, path)?)
+    }
+}
+
+#[test]
+fn store_dedup() {
+    let dir = std::env::temp_dir().join("pagepal_store_dedup");
+    let mut s = Store::open(&dir).unwrap();
+    let a = s.put(b"credits").unwrap();
+    assert_eq!(s.put(b"credits").unwrap(), a);
+    assert_eq!(s.refs(&a), 2);
+    let b = s.put(b"page").unwrap();
+    s.flush().unwrap();
+    let mut s = Store::open(&dir).unwrap();
+    assert!(!s.release(&a).unwrap() && s.path(&a).exists());
+    fs::write(s.path(&b), b"pagf").unwrap();
+    assert_eq!(s.verify().unwrap(), vec![b]);
+    assert!(s.release(&a).unwrap() && !s.path(&a).exists());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,93 @@
+use crate::{Error, Format};
+use image::{
+    imageops::FilterType,
+    DynamicImage,
+    GenericImageView,
+    ImageOutputFormat,
+};
+use serde::{Deserialize as des, Serialize as ser};
+
+/// How downloaded images of a book are re-encoded to save space. Encoding
+/// drops any metadata the image had.
+#[derive(Clone, Debug, PartialEq, Eq, ser, des)]
+#[serde(default)]
+pub struct Transcode {
+    /// Only `Jpeg` and `Png` can be written, anything else is written as
+    /// `Jpeg`
+    pub format:     Format,
+    /// JPEG quality from 1 to 100
+    pub quality:    u8,
+    /// Wider images are scaled down to this width
+    pub max_width:  Option<u32>,
+    /// Taller images, like webtoon strips, are cut into pages of this height
+    pub max_height: Option<u32>,
+}
+impl Default for Transcode {
+    fn default() -> Self {
+        Self {
+            format:     Format::Jpeg,
+            quality:    85,
+            max_width:  None,
+            max_height: None,
+        }
+    }
+}
+impl Transcode {
+    /// Re-encodes the image, scaled and cut into pages, returning the format
+    /// the pages were written in
+    pub fn apply(&self, data: &[u8]) -> Result<(Format, Vec<Vec<u8>>), Error> {
+        let mut img = image::load_from_memory(data)
+            .map_err(|e| Error::Parse(format!("image: {}", e)))?;
+        if let Some(w) = self.max_width.filter(|&w| w > 0 && img.width() > w) {
+            img = img.resize(w, u32::MAX, FilterType::Lanczos3);
+        }
+        let (w, h) = img.dimensions();
+        let step = self.max_height.filter(|&a| a > 0).unwrap_or(h).max(1);
+        let mut pages = vec![];
+        let mut y = 0;
+        while y < h {
+            pages.push(self.encode(&img.crop_imm(0, y, w, step.min(h - y)))?);
+            y += step;
+        }
+        Ok((self.output(), pages))
+    }
+
+    fn output(&self) -> Format {
+        match self.format {
+            Format::Png => Format::Png,
+            _ => Format::Jpeg,
+        }
+    }
+
+    fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        match self.output() {
+            Format::Png => img.write_to(&mut buf, ImageOutputFormat::Png),
+            _ => DynamicImage::ImageRgb8(img.to_rgb8()).write_to(
+                &mut buf,
+                ImageOutputFormat::Jpeg(self.quality.clamp(1, 100)),
+            ),
+        }
+        .map_err(|e| Error::Parse(format!("image: {}", e)))?;
+        Ok(buf)
+    }
+}
+
+#[test]
+fn transcode_strip() {
+    let mut png = vec![];
+    DynamicImage::new_rgb8(10, 50)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .unwrap();
+    let t = Transcode {
+        max_width: Some(5),
+        max_height: Some(10),
+        ..Default::default()
+    };
+    let (format, pages) = t.apply(&png).unwrap();
+    assert_eq!(format, Format::Jpeg);
+    assert_eq!(pages.len(), 3);
+    assert!(pages.iter().all(|a| Format::sniff(a) == Some(Format::Jpeg)));
+    let last = image::load_from_memory(&pages[2]).unwrap();
+    assert_eq!(last.dimensions(), (5, 5));
+}
@@ -1 +1,160 @@
+use crate::{library::blobs, Label, Library};
+use conrod_core::{image::Map as ImageMap, text::font::Id as FontId, Ui, UiCell};
+use piston_window::{G2dTexture, G2dTextureContext, Key};
 
+pub mod manga;
+pub mod novel;
+
+pub use self::{manga::*, novel::*};
+
+/// What a key press asks of a reader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    NextPage,
+    PrevPage,
+    NextChapter,
+    PrevChapter,
+    First,
+    Last,
+    ScrollUp,
+    ScrollDown,
+    Zoom(Zoom),
+    CycleZoom,
+    Bigger,
+    Smaller,
+    Wider,
+    Narrower,
+    Spacing,
+    Margin,
+    ToggleScroll,
+}
+
+impl Action {
+    /// The reader's key bindings
+    pub fn from_key(key: Key) -> Option<Self> {
+        Some(match key {
+            Key::Right | Key::Space => Action::NextPage,
+            Key::Left | Key::Backspace => Action::PrevPage,
+            Key::PageDown | Key::N | Key::RightBracket => Action::NextChapter,
+            Key::PageUp | Key::P | Key::LeftBracket => Action::PrevChapter,
+            Key::Home => Action::First,
+            Key::End => Action::Last,
+            Key::Up => Action::ScrollUp,
+            Key::Down => Action::ScrollDown,
+            Key::W => Action::Zoom(Zoom::FitWidth),
+            Key::H => Action::Zoom(Zoom::FitHeight),
+            Key::O => Action::Zoom(Zoom::Original),
+            Key::Z => Action::CycleZoom,
+            Key::Equals | Key::NumPadPlus => Action::Bigger,
+            Key::Minus | Key::NumPadMinus => Action::Smaller,
+            Key::Period => Action::Wider,
+            Key::Comma => Action::Narrower,
+            Key::L => Action::Spacing,
+            Key::M => Action::Margin,
+            Key::S => Action::ToggleScroll,
+            _ => return None,
+        })
+    }
+}
+
+/// The reader of whichever kind of book is open
+pub enum Reader {
+    Manga(MangaReader),
+    Novel(NovelReader),
+}
+
+impl Reader {
+    /// Opens the book called `label`, novels are set with `font` and `style`
+    pub fn open(
+        library: &Library, label: &Label, ui: &mut Ui, font: FontId,
+        style: Typography,
+    ) -> Option<Self> {
+        let ids = ui.widget_id_generator();
+        if let Some(book) = library.manga.get(label) {
+            return Some(Reader::Manga(MangaReader::new(
+                book,
+                MangaIds::new(ids),
+            )));
+        }
+        let book = library.novels.get(label)?;
+        Some(Reader::Novel(NovelReader::new(
+            book,
+            NovelIds::new(ids),
+            font,
+            style,
+        )))
+    }
+
+    /// Frees what the reader uploaded to the GPU
+    pub fn close(&mut self, map: &mut ImageMap<G2dTexture>) {
+        if let Reader::Manga(r) = self {
+            r.close(map);
+        }
+    }
+
+    pub fn title(&self) -> &Label {
+        match self {
+            Reader::Manga(r) => &r.title,
+            Reader::Novel(r) => &r.title,
+        }
+    }
+
+    /// Typography of the novel being read
+    pub fn style(&self) -> Option<&Typography> {
+        match self {
+            Reader::Manga(_) => None,
+            Reader::Novel(r) => Some(&r.style),
+        }
+    }
+
+    /// Handles a reader action, returning whether anything changed
+    pub fn act(&mut self, action: Action, library: &mut Library) -> bool {
+        match self {
+            Reader::Manga(r) => library
+                .manga
+                .get_mut(&r.title)
+                .map_or(false, |b| r.act(action, b)),
+            Reader::Novel(r) => library
+                .novels
+                .get_mut(&r.title)
+                .map_or(false, |b| r.act(action, b)),
+        }
+    }
+
+    /// Scrolls by `dy` mouse wheel notches, positive is up
+    pub fn wheel(&mut self, dy: f64, library: &mut Library) -> bool {
+        match self {
+            Reader::Manga(r) => r.scroll(-dy * 40.0),
+            Reader::Novel(_) if dy > 0.0 => self.act(Action::ScrollUp, library),
+            Reader::Novel(_) if dy < 0.0 => self.act(Action::ScrollDown, library),
+            Reader::Novel(_) => false,
+        }
+    }
+
+    /// Sets the widgets of the reader for this frame, uploading pages of
+    /// manga as textures
+    pub fn set(
+        &mut self, ui: &mut UiCell, library: &mut Library,
+        ctx: &mut G2dTextureContext, map: &mut ImageMap<G2dTexture>,
+    ) {
+        let store = blobs(&mut library.blobs, &library.dir);
+        match (self, store) {
+            (Reader::Manga(r), Ok(store)) => {
+                if let Some(book) = library.manga.get(&r.title) {
+                    r.load(book, store, ctx, map);
+                }
+                r.set(ui);
+            }
+            (Reader::Novel(r), Ok(store)) => {
+                if let Some(book) = library.novels.get_mut(&r.title) {
+                    r.set(ui, book, store);
+                }
+            }
+            (Reader::Manga(r), Err(e)) => {
+                r.error = Some(e.to_string());
+                r.set(ui);
+            }
+            (Reader::Novel(r), Err(e)) => r.error = Some(e.to_string()),
+        }
+    }
+}
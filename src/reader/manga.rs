@@ -0,0 +1,303 @@
+use crate::{
+    reader::Action,
+    ui::texture,
+    Book,
+    Error,
+    Label,
+    Manga,
+    Media,
+    Num,
+    Store,
+};
+use chrono::Utc;
+use conrod_core::{
+    color,
+    image::{Id as ImageId, Map as ImageMap},
+    widget,
+    widget_ids,
+    Colorable,
+    Positionable,
+    Sizeable,
+    UiCell,
+    Widget,
+};
+use piston_window::{G2dTexture, G2dTextureContext};
+
+/// Pixels scrolled by a key press
+const SCROLL: f64 = 80.0;
+
+widget_ids! {
+    pub struct MangaIds { canvas, page, status }
+}
+
+/// How a page is scaled to the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zoom {
+    FitWidth,
+    FitHeight,
+    Original,
+}
+impl Default for Zoom {
+    fn default() -> Self { Zoom::FitWidth }
+}
+impl Zoom {
+    pub fn next(self) -> Self {
+        match self {
+            Zoom::FitWidth => Zoom::FitHeight,
+            Zoom::FitHeight => Zoom::Original,
+            Zoom::Original => Zoom::FitWidth,
+        }
+    }
+
+    /// Size an image of `size` is shown at in a view of `view`
+    pub fn fit(self, size: [f64; 2], view: [f64; 2]) -> [f64; 2] {
+        let scale = match self {
+            Zoom::FitWidth if size[0] > 0.0 => view[0] / size[0],
+            Zoom::FitHeight if size[1] > 0.0 => view[1] / size[1],
+            _ => 1.0,
+        };
+        [size[0] * scale, size[1] * scale]
+    }
+}
+
+/// A single page of a book: the content it's in, which of the content's
+/// parts it is and its chapter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Leaf {
+    pub num:  Num,
+    pub part: usize,
+    pub ch:   Option<u16>,
+}
+
+/// The pages of a book in reading order and the one being read
+#[derive(Clone, Debug, Default)]
+pub struct Pager {
+    leaves: Vec<Leaf>,
+    pos:    usize,
+}
+impl Pager {
+    /// Pages of the book, starting at its read position
+    pub fn new<T: Media>(book: &Book<T>) -> Self {
+        let leaves = book
+            .content
+            .iter()
+            .flat_map(|(num, c)| {
+                (0..=c.parts).map(move |part| Leaf {
+                    num: num.clone(),
+                    part,
+                    ch: c.ch,
+                })
+            })
+            .collect::<Vec<_>>();
+        let pos = (book.pos as usize).min(leaves.len().saturating_sub(1));
+        Self { leaves, pos }
+    }
+
+    pub fn len(&self) -> usize { self.leaves.len() }
+
+    pub fn is_empty(&self) -> bool { self.leaves.is_empty() }
+
+    pub fn pos(&self) -> usize { self.pos }
+
+    pub fn current(&self) -> Option<&Leaf> { self.leaves.get(self.pos) }
+
+    /// First page of the chapter page `i` is in
+    fn start(&self, i: usize) -> usize {
+        let ch = self.leaves[i].ch;
+        self.leaves[..i]
+            .iter()
+            .rposition(|a| a.ch != ch)
+            .map_or(0, |a| a + 1)
+    }
+
+    /// Moves to page `i`, or the last one, returning whether the page changed
+    pub fn go(&mut self, i: usize) -> bool {
+        let i = i.min(self.leaves.len().saturating_sub(1));
+        std::mem::replace(&mut self.pos, i) != i
+    }
+
+    /// Turns pages, returning whether the page changed. Chapters move to
+    /// their first page.
+    pub fn apply(&mut self, action: Action) -> bool {
+        if self.leaves.is_empty() {
+            return false;
+        }
+        match action {
+            Action::NextPage => self.go(self.pos + 1),
+            Action::PrevPage => self.go(self.pos.saturating_sub(1)),
+            Action::NextChapter => {
+                let ch = self.leaves[self.pos].ch;
+                match self.leaves[self.pos..].iter().position(|a| a.ch != ch) {
+                    Some(n) => self.go(self.pos + n),
+                    None => false,
+                }
+            }
+            Action::PrevChapter => match self.start(self.pos) {
+                0 => self.go(0),
+                s => self.go(self.start(s - 1)),
+            },
+            Action::First => self.go(0),
+            Action::Last => self.go(self.leaves.len() - 1),
+            _ => false,
+        }
+    }
+}
+
+/// Shows the pages of a manga one at a time
+pub struct MangaReader {
+    pub title:        Label,
+    pub pager:        Pager,
+    pub zoom:         Zoom,
+    ids:              MangaIds,
+    /// Page the texture is of, its id and the page's size
+    shown:            Option<(usize, ImageId, [f64; 2])>,
+    /// How far down an overflowing page is scrolled
+    scroll:           f64,
+    pub(crate) error: Option<String>,
+}
+impl MangaReader {
+    pub fn new(book: &Book<Manga>, ids: MangaIds) -> Self {
+        Self {
+            title: book.title.to_owned(),
+            pager: Pager::new(book),
+            zoom: Zoom::default(),
+            ids,
+            shown: None,
+            scroll: 0.0,
+            error: None,
+        }
+    }
+
+    /// Handles a reader action, writing the read position back to the book.
+    /// Returns whether anything changed.
+    pub fn act(&mut self, action: Action, book: &mut Book<Manga>) -> bool {
+        match action {
+            Action::Zoom(z) => {
+                self.zoom = z;
+                self.scroll = 0.0;
+                true
+            }
+            Action::CycleZoom => self.act(Action::Zoom(self.zoom.next()), book),
+            Action::ScrollUp => self.scroll(-SCROLL),
+            Action::ScrollDown => self.scroll(SCROLL),
+            a => {
+                let moved = self.pager.apply(a);
+                if moved {
+                    self.scroll = 0.0;
+                    book.pos = self.pager.pos() as u32;
+                    book.last_read = Some(Utc::now());
+                }
+                moved
+            }
+        }
+    }
+
+    /// Scrolls an overflowing page, positive is down
+    pub fn scroll(&mut self, dy: f64) -> bool {
+        self.scroll = (self.scroll + dy).max(0.0);
+        true
+    }
+
+    /// Uploads the current page as a texture, replacing the previous page's
+    pub fn load(
+        &mut self, book: &Book<Manga>, store: &Store,
+        ctx: &mut G2dTextureContext, map: &mut ImageMap<G2dTexture>,
+    ) {
+        let pos = self.pager.pos();
+        if self.pager.is_empty() || self.shown.map(|a| a.0) == Some(pos) {
+            return;
+        }
+        if let Some((_, id, _)) = self.shown.take() {
+            map.remove(id);
+        }
+        self.error = None;
+        match self.texture(book, store, ctx) {
+            Ok((tex, size)) => self.shown = Some((pos, map.insert(tex), size)),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    fn texture(
+        &self, book: &Book<Manga>, store: &Store, ctx: &mut G2dTextureContext,
+    ) -> Result<(G2dTexture, [f64; 2]), Error> {
+        let missing = || Error::Missing(format!("page {}", self.pager.pos() + 1));
+        let leaf = self.pager.current().ok_or_else(missing)?;
+        let pages = book
+            .content
+            .get(&leaf.num)
+            .ok_or_else(missing)?
+            .pages(&book.dir(), store)?;
+        texture(ctx, pages.get(leaf.part).ok_or_else(missing)?, None)
+    }
+
+    /// Drops the texture of the page shown
+    pub fn close(&mut self, map: &mut ImageMap<G2dTexture>) {
+        if let Some((_, id, _)) = self.shown.take() {
+            map.remove(id);
+        }
+    }
+
+    /// Sets the widgets of the reader for this frame
+    pub fn set(&mut self, ui: &mut UiCell) {
+        let view = [ui.win_w, ui.win_h];
+        widget::Canvas::new()
+            .color(color::BLACK)
+            .set(self.ids.canvas, ui);
+        if let Some((_, id, size)) = self.shown {
+            let [w, h] = self.zoom.fit(size, view);
+            let over = [(w - view[0]).max(0.0), (h - view[1]).max(0.0)];
+            self.scroll = self.scroll.min(over[1]);
+            // Overflowing pages start at their top left corner
+            widget::Image::new(id)
+                .w_h(w, h)
+                .x_y(over[0] / 2.0, self.scroll - over[1] / 2.0)
+                .set(self.ids.page, ui);
+        }
+        let mut status = match self.pager.current() {
+            Some(leaf) => format!(
+                "{}  |  {}page {}/{}  |  {:?}",
+                self.title.0.trim(),
+                leaf.ch
+                    .map_or(String::new(), |a| format!("chapter {}, ", a + 1)),
+                self.pager.pos() + 1,
+                self.pager.len(),
+                self.zoom
+            ),
+            None => format!("{} has no pages yet", self.title.0.trim()),
+        };
+        if let Some(e) = &self.error {
+            status += &format!("  |  {}", e);
+        }
+        widget::Text::new(&status)
+            .font_size(12)
+            .color(color::WHITE)
+            .bottom_left_with_margin_on(self.ids.canvas, 6.0)
+            .set(self.ids.status, ui);
+    }
+}
+
+#[test]
+fn pager_navigation() {
+    use crate::Content;
+    let mut book: Book<Manga> = Book::default();
+    for (n, ch, parts) in &[(0, 0, 0), (1, 0, 1), (2, 1, 0), (3, 2, 0)] {
+        let mut c = Content::default();
+        c.ch = Some(*ch);
+        c.parts = *parts;
+        book.content.insert(Num(*n, None), c);
+    }
+    book.pos = 2;
+    let mut p = Pager::new(&book);
+    assert_eq!(p.len(), 5);
+    assert_eq!(p.current().map(|a| a.part), Some(1));
+    assert!(p.apply(Action::NextChapter));
+    assert_eq!(p.pos(), 3);
+    assert!(p.apply(Action::PrevChapter));
+    assert_eq!(p.pos(), 0);
+    assert!(p.apply(Action::Last));
+    assert!(!p.apply(Action::NextPage));
+    assert!(!p.apply(Action::NextChapter));
+    assert_eq!(Zoom::FitHeight.fit([100.0, 400.0], [800.0, 200.0]), [
+        50.0, 200.0
+    ]);
+}
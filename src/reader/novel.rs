@@ -0,0 +1,364 @@
+use crate::{
+    reader::{Action, Pager},
+    Book,
+    Error,
+    Label,
+    Novel,
+    Store,
+};
+use chrono::Utc;
+use conrod_core::{
+    color,
+    text::{font::Id as FontId, line, Font},
+    widget,
+    widget_ids,
+    Colorable,
+    FontSize,
+    Positionable,
+    Scalar,
+    Sizeable,
+    UiCell,
+    Widget,
+};
+use serde::{Deserialize as des, Serialize as ser};
+use std::{fs, ops::Range, path::Path};
+
+/// Room kept for the status line under the text
+const STATUS: f64 = 24.0;
+
+widget_ids! {
+    pub struct NovelIds { canvas, text, status }
+}
+
+/// How the text of a novel is set
+#[derive(Clone, Debug, PartialEq, ser, des)]
+#[serde(default)]
+pub struct Typography {
+    pub font_size:    FontSize,
+    /// Line height as a multiple of the font size
+    pub line_spacing: f64,
+    /// Space around the text on every side
+    pub margin:       f64,
+    /// Widest the column of text gets on wide windows
+    pub page_width:   f64,
+    /// Scroll line by line instead of turning whole pages
+    pub scroll:       bool,
+}
+impl Default for Typography {
+    fn default() -> Self {
+        Self {
+            font_size:    18,
+            line_spacing: 1.5,
+            margin:       40.0,
+            page_width:   720.0,
+            scroll:       false,
+        }
+    }
+}
+impl Typography {
+    /// Reads the settings from a `.toml` file, the defaults if there's none
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|a| toml::from_str(&a).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn store(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let s = toml::to_string(self).map_err(|e| Error::Parse(e.to_string()))?;
+        Ok(fs::write(path, s)?)
+    }
+
+    /// Changes the settings, returning whether the action was one of them
+    pub fn apply(&mut self, action: Action) -> bool {
+        match action {
+            Action::Bigger => self.font_size = (self.font_size + 2).min(48),
+            Action::Smaller => self.font_size = self.font_size.max(10) - 2,
+            Action::Wider => self.page_width += 40.0,
+            Action::Narrower => {
+                self.page_width = (self.page_width - 40.0).max(200.0)
+            }
+            Action::Spacing => {
+                self.line_spacing = match self.line_spacing {
+                    a if a < 1.2 => 1.2,
+                    a if a < 1.5 => 1.5,
+                    a if a < 2.0 => 2.0,
+                    _ => 1.0,
+                }
+            }
+            Action::Margin => {
+                self.margin = match self.margin {
+                    a if a < 20.0 => 20.0,
+                    a if a < 40.0 => 40.0,
+                    a if a < 80.0 => 80.0,
+                    _ => 0.0,
+                }
+            }
+            Action::ToggleScroll => self.scroll = !self.scroll,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Height of a line of text
+    pub fn line_height(&self) -> f64 { self.font_size as f64 * self.line_spacing }
+
+    /// Width of the column of text in a view of `view`
+    pub fn width(&self, view: [f64; 2]) -> f64 {
+        self.page_width.min(view[0] - 2.0 * self.margin).max(1.0)
+    }
+
+    /// Lines of text that fit in a view of `view`
+    pub fn rows(&self, view: [f64; 2]) -> usize {
+        ((view[1] - 2.0 * self.margin - STATUS) / self.line_height()).max(1.0)
+            as usize
+    }
+}
+
+/// Byte ranges of the lines `text` wraps into at `width`
+pub fn wrap(
+    text: &str, font: &Font, size: FontSize, width: Scalar,
+) -> Vec<Range<usize>> {
+    line::infos(text, font, size)
+        .wrap_by_whitespace(width)
+        .map(|a| a.byte_range())
+        .collect()
+}
+
+/// Shows the text of a novel a page, or a few lines, at a time
+pub struct NovelReader {
+    pub title:        Label,
+    pub pager:        Pager,
+    pub style:        Typography,
+    ids:              NovelIds,
+    font:             FontId,
+    /// Content the text is of and the text
+    text:             Option<(usize, String)>,
+    lines:            Vec<Range<usize>>,
+    /// Font size and width the lines were wrapped at
+    laid:             Option<(FontSize, u64)>,
+    /// First line shown
+    top:              usize,
+    /// Byte offset of the first line shown, `usize::MAX` for the last page
+    offset:           usize,
+    /// Lines shown at once
+    rows:             usize,
+    pub(crate) error: Option<String>,
+}
+impl NovelReader {
+    pub fn new(
+        book: &Book<Novel>, ids: NovelIds, font: FontId, style: Typography,
+    ) -> Self {
+        Self {
+            title: book.title.to_owned(),
+            pager: Pager::new(book),
+            style,
+            ids,
+            font,
+            text: None,
+            lines: vec![],
+            laid: None,
+            top: 0,
+            offset: book.offset as usize,
+            rows: 1,
+            error: None,
+        }
+    }
+
+    /// Moves to line `top`, returning whether it changed
+    fn go(&mut self, top: usize) -> bool {
+        let top = top.min(self.lines.len().saturating_sub(1));
+        self.offset = self.lines.get(top).map_or(0, |a| a.start);
+        std::mem::replace(&mut self.top, top) != top
+    }
+
+    /// Turns to the next or previous content of the book
+    fn turn(&mut self, action: Action, offset: usize) -> bool {
+        let moved = self.pager.apply(action);
+        if moved {
+            self.offset = offset;
+        }
+        moved
+    }
+
+    /// Handles a reader action, writing the read position back to the book.
+    /// Returns whether anything changed.
+    pub fn act(&mut self, action: Action, book: &mut Book<Novel>) -> bool {
+        let step = match self.style.scroll {
+            true => 1,
+            false => self.rows,
+        };
+        let (pos, offset) = (book.pos, book.offset);
+        let changed = match action {
+            Action::NextPage | Action::ScrollDown
+                if self.top + self.rows < self.lines.len() =>
+            {
+                let by = match action {
+                    Action::NextPage => self.rows,
+                    _ => step,
+                };
+                self.go(self.top + by)
+            }
+            Action::PrevPage | Action::ScrollUp if self.top > 0 => {
+                let by = match action {
+                    Action::PrevPage => self.rows,
+                    _ => step,
+                };
+                self.go(self.top.saturating_sub(by))
+            }
+            Action::NextPage | Action::ScrollDown => {
+                self.turn(Action::NextPage, 0)
+            }
+            Action::PrevPage | Action::ScrollUp => {
+                self.turn(Action::PrevPage, usize::MAX)
+            }
+            Action::Zoom(_) | Action::CycleZoom => false,
+            a if self.style.apply(a) => true,
+            a => self.turn(a, 0),
+        };
+        book.pos = self.pager.pos() as u32;
+        book.offset = self.offset.min(u32::MAX as usize) as u32;
+        if (book.pos, book.offset) != (pos, offset) {
+            book.last_read = Some(Utc::now());
+        }
+        changed
+    }
+
+    /// Reads the text of the current content if it isn't loaded yet
+    fn load(&mut self, book: &Book<Novel>, store: &Store) {
+        let pos = self.pager.pos();
+        if self.pager.is_empty() || self.text.as_ref().map(|a| a.0) == Some(pos) {
+            return;
+        }
+        self.laid = None;
+        self.error = None;
+        let text = self
+            .pager
+            .current()
+            .and_then(|a| book.content.get(&a.num))
+            .ok_or_else(|| Error::Missing(format!("content {}", pos + 1)))
+            .and_then(|c| c.bytes(&book.dir(), store).map(|a| a.into_owned()));
+        match text {
+            Ok(a) => {
+                self.text = Some((pos, String::from_utf8_lossy(&a).into_owned()))
+            }
+            Err(e) => {
+                self.text = Some((pos, String::new()));
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Wraps the text again if the font size or width changed, staying at
+    /// the read position
+    fn layout(&mut self, font: &Font, width: f64) {
+        let key = (self.style.font_size, width.to_bits());
+        if self.laid == Some(key) {
+            return;
+        }
+        let text = self.text.as_ref().map_or("", |a| a.1.as_str());
+        self.lines = wrap(text, font, self.style.font_size, width);
+        self.laid = Some(key);
+        self.top = match self.offset {
+            usize::MAX => {
+                let last = self.lines.len().saturating_sub(1);
+                match self.style.scroll {
+                    true => self.lines.len().saturating_sub(self.rows),
+                    false => last - last % self.rows,
+                }
+            }
+            o => self.lines.iter().rposition(|a| a.start <= o).unwrap_or(0),
+        };
+        self.offset = self.lines.get(self.top).map_or(0, |a| a.start);
+    }
+
+    /// Sets the widgets of the reader for this frame
+    pub fn set(
+        &mut self, ui: &mut UiCell, book: &mut Book<Novel>, store: &Store,
+    ) {
+        let view = [ui.win_w, ui.win_h];
+        let width = self.style.width(view);
+        self.rows = self.style.rows(view);
+        self.load(book, store);
+        if let Some(font) = ui.fonts.get(self.font) {
+            self.layout(font, width);
+        }
+        book.pos = self.pager.pos() as u32;
+        book.offset = self.offset.min(u32::MAX as usize) as u32;
+
+        widget::Canvas::new()
+            .color(color::DARK_CHARCOAL)
+            .set(self.ids.canvas, ui);
+        let text = self.text.as_ref().map_or("", |a| a.1.as_str());
+        let shown = self
+            .lines
+            .iter()
+            .skip(self.top)
+            .take(self.rows)
+            .map(|a| &text[a.clone()])
+            .collect::<Vec<_>>()
+            .join("\n");
+        let extra = self.style.line_height() - self.style.font_size as f64;
+        widget::Text::new(&shown)
+            .font_id(self.font)
+            .font_size(self.style.font_size)
+            .line_spacing(extra.max(0.0))
+            .no_line_wrap()
+            .w(width)
+            .color(color::WHITE)
+            .mid_top_with_margin_on(self.ids.canvas, self.style.margin)
+            .set(self.ids.text, ui);
+
+        let mut status = match self.pager.current() {
+            Some(leaf) => format!(
+                "{}  |  {}{}  |  {}",
+                self.title.0.trim(),
+                leaf.ch
+                    .map_or(String::new(), |a| format!("chapter {}, ", a + 1)),
+                match self.style.scroll {
+                    true => format!("line {}/{}", self.top + 1, self.lines.len()),
+                    false => format!(
+                        "page {}/{}",
+                        self.top / self.rows + 1,
+                        (self.lines.len() + self.rows - 1) / self.rows
+                    ),
+                },
+                match self.style.scroll {
+                    true => "scrolling",
+                    false => "paged",
+                }
+            ),
+            None => format!("{} has no text yet", self.title.0.trim()),
+        };
+        if let Some(e) = &self.error {
+            status += &format!("  |  {}", e);
+        }
+        widget::Text::new(&status)
+            .font_size(12)
+            .color(color::LIGHT_GREY)
+            .bottom_left_with_margin_on(self.ids.canvas, 6.0)
+            .set(self.ids.status, ui);
+    }
+}
+
+#[test]
+fn novel_layout() {
+    let font = conrod_core::text::font::from_file("assets/NotoSans-Regular.ttf")
+        .unwrap();
+    let text = "One two three four five six seven eight nine ten\n\nEleven";
+    let narrow = wrap(text, &font, 18, 120.0);
+    let wide = wrap(text, &font, 18, 2000.0);
+    assert!(narrow.len() > wide.len());
+    assert_eq!(wide.len(), 3);
+    assert_eq!(&text[wide[2].clone()], "Eleven");
+    assert!(narrow.iter().all(|a| !text[a.clone()].contains('\n')));
+
+    let mut style = Typography::default();
+    assert_eq!(style.rows([800.0, 600.0]), 18);
+    assert!(style.apply(Action::Bigger));
+    assert!(style.rows([800.0, 600.0]) < 18);
+    assert!(!style.apply(Action::NextPage));
+}
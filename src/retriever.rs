@@ -1,145 +1,459 @@
-use self::delay::Delay;
-use crate::{Book, Content, Media};
-use futures::future::join_all;
+use self::{
+    backoff::{Backoff, Failures},
+    delay::Delay,
+};
+use crate::{Book, Chapter, Content, Error, Format, Label, Media, Store};
+use futures::{stream, Future, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize as des, Serialize as ser};
-#[cfg(feature = "trait_ojb_ser")] use serde_traitobject as s;
 use serde_with::serde_as;
-#[cfg(feature = "trait_ojb_ser")] use std::rc::Rc;
 use std::{
     collections::{
         hash_map::Entry::{Occupied, Vacant},
         BTreeMap,
         HashMap,
+        HashSet,
     },
     fmt::Debug,
+    path::{Path, PathBuf},
     sync::Arc,
 };
-use tokio::sync::Mutex;
-use url::Host;
+use tokio::{
+    sync::{Mutex, OwnedSemaphorePermit},
+    time::{sleep, sleep_until},
+};
+use url::{Host, Url};
 
+pub mod backoff;
 pub mod delay;
+pub mod events;
 pub mod finder;
 pub mod headers;
+pub mod limit;
 pub mod page;
+pub mod queue;
+pub mod rules;
+pub mod sites;
 
-pub use self::{finder::*, headers::*, page::*};
-#[derive(Clone, Default, ser, des)]
-pub struct FindWrap(
-    #[cfg(feature = "trait_ojb_ser")]
-    #[serde(with = "serde_traitobject")]
-    pub Rc<Option<s::Box<dyn Finder>>>,
-);
+pub use self::{
+    events::*,
+    finder::*,
+    headers::*,
+    limit::*,
+    page::*,
+    queue::*,
+    rules::SiteDef,
+};
+
+/// What `Retriever::update` found on the index page
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Update {
+    /// Ids of the chapters added to the book
+    pub added:   Vec<u16>,
+    /// New chapters that couldn't be downloaded
+    pub skipped: usize,
+}
 
 #[serde_as]
 #[derive(Clone, Default, ser, des)]
 pub struct Retriever {
-    headers: BTreeMap<Host, Headers>,
+    headers:  BTreeMap<Host, Headers>,
+    #[serde(default)]
+    limits:   BTreeMap<Host, RateLimit>,
+    #[serde(skip)]
+    client:   Client,
     #[serde(skip)]
-    client:  Client,
+    sites:    Arc<Mutex<HashMap<Host, Delay>>>,
     #[serde(skip)]
-    sites:   Arc<Mutex<HashMap<Host, Delay>>>,
-    #[cfg(feature = "trait_ojb_ser")]
-    #[serde_as(as = "Vec<(_, _)>")]
-    finders: BTreeMap<Host, FindWrap>,
+    failures: Arc<Mutex<HashMap<Host, Failures>>>,
+    #[serde(default)]
+    backoff:  Backoff,
+    #[serde(default)]
+    jobs:     Jobs,
+    #[serde(skip)]
+    finders:  BTreeMap<Host, SiteFinder>,
+    #[serde(skip)]
+    events:   Events,
     //add new fields to the Debug impl
 }
 /// Struct for download logic
 impl Retriever {
-    pub async fn refresh(&self, page: &Page) -> Page {
-        self.access(page).await;
-        if !page.full.get() {
-            self.dl(page).await.unwrap()
-        } else {
-            if page.is_old(None) {}
-            page.refresh(Some(&self.client)).await.unwrap()
+    pub async fn refresh(&self, page: &Page) -> Result<Page, Error> {
+        if let Some(f) = self.finder(&page.domain()?) {
+            page.set_finder(f);
         }
+        self.retry(page, || async move {
+            match page.is_full() && page.has_request() {
+                true => page.refresh(Some(&self.client)).await,
+                false => self.dl(page).await,
+            }
+        })
+        .await
+    }
+
+    /// Downloads the bytes behind a Page, e.g. an image, and the format the
+    /// server says it's in
+    pub async fn image(
+        &self, page: &Page,
+    ) -> Result<(Vec<u8>, Option<Format>), Error> {
+        self.retry(page, || page.get_image(&self.client)).await
     }
 
     /// Generates a Book<T> from a page to either
-    /// a chapter or the index of the book
-    pub async fn book<T: Debug + Media + Clone>(&self, page: Page) -> Book<T> {
-        let index = self.index(&page).await;
+    /// a chapter or the index of the book.
+    /// Chapters and pages that fail to download are left out
+    pub async fn book<T: Debug + Media + Clone>(
+        &self, page: Page,
+    ) -> Result<Book<T>, Error> {
+        let index = self.index(&page).await?;
         let title = index.title();
-        let chapters = self
-            .chapters(&index)
-            .await
+        self.indexed(&title, &index);
+        let chapters = self.chapters(&index).await?;
+        let mut bk = Book {
+            title,
+            meta: index.metadata(),
+            index,
+            ..Default::default()
+        };
+        self.fill(&mut bk, chapters).await;
+        Ok(bk)
+    }
+
+    /// Re-fetches the index of `bk` and downloads only the chapters that
+    /// aren't in it yet
+    pub async fn update<T: Debug + Media + Clone>(
+        &self, bk: &mut Book<T>,
+    ) -> Result<Update, Error> {
+        let index = self.refresh(&bk.index).await?;
+        self.indexed(&bk.title, &index);
+        let known = bk
+            .chs
+            .values()
+            .filter_map(|c| c.src.as_ref())
+            .chain(bk.content.values().filter_map(|c| c.src.as_ref()))
+            .map(|p| p.loc.to_owned())
+            .collect::<HashSet<_>>();
+        let new = self
+            .links(&index)?
             .into_iter()
-            .map(|a| (a.get_content::<T>().unwrap().clone(), a));
-        let mut contents = vec![];
-        match T::visual() {
-            true => {
-                for (c, _) in chapters {
-                    contents.extend(
-                        join_all(c.iter().map(|a| async move {
-                            let page = self.refresh(&Page::from(a)).await;
-                            (
-                                page.clone(),
-                                Content::from(page.get_image(&self.client).await),
-                            )
-                                .into()
-                        }))
-                        .await,
-                    )
+            .filter(|a| !known.contains(a))
+            .collect::<Vec<_>>();
+        let found = new.len();
+        let chapters = self.pages(new).await;
+        bk.meta = index.metadata();
+        bk.index = index;
+        let added = self.fill(bk, chapters).await;
+        Ok(Update {
+            skipped: found - added.len(),
+            added,
+        })
+    }
+
+    /// Downloads the content of the chapter pages into `bk`, returning the ids
+    /// of the chapters that were added. Chapters without any content that
+    /// could be downloaded aren't.
+    async fn fill<T: Debug + Media + Clone>(
+        &self, bk: &mut Book<T>, chapters: Vec<Page>,
+    ) -> Vec<u16> {
+        let mut added = vec![];
+        for page in chapters {
+            let c = match page.get_content::<T>() {
+                Some(c) => c,
+                None => continue,
+            };
+            let id = bk.next_chapter();
+            let contents: Vec<(usize, Content<T>)> = match T::visual() {
+                true => {
+                    let urls = c
+                        .iter()
+                        .filter_map(|a| page.loc.join(a).ok())
+                        .collect::<Vec<_>>();
+                    self.found(&bk.title, id, &page, urls.len());
+                    let title = &bk.title;
+                    let status = DownloadStatus::Queued(urls.len());
+                    self.emit(title, Some(id), &page.loc, 0, status);
+                    stream::iter(urls.into_iter().enumerate().map(
+                        |(n, a)| async move {
+                            let res = self.content(a.to_owned()).await;
+                            self.finished(title, id, &a, &res);
+                            Some((n, res.ok()?))
+                        },
+                    ))
+                    .buffered(self.jobs.get())
+                    .filter_map(|a| async move { a })
+                    .collect()
+                    .await
                 }
+                false => {
+                    let text = c.join("\n\n");
+                    let (title, loc) = (&bk.title, &page.loc);
+                    self.found(title, id, &page, 1);
+                    self.emit(title, Some(id), loc, 0, DownloadStatus::Queued(1));
+                    self.emit(
+                        title,
+                        Some(id),
+                        loc,
+                        text.len(),
+                        DownloadStatus::Done,
+                    );
+                    vec![(0, Content::from((page.clone(), Content::from(text))))]
+                }
+            };
+            let mut filed = 0;
+            for (n, mut a) in contents {
+                bk.process(&mut a);
+                // Past the pages a chapter can hold, like a failed download
+                filed += bk.add_content(id, n, a).is_ok() as usize;
+            }
+            // Left for the next update to try again
+            if filed == 0 {
+                continue;
             }
-            false => contents.extend(chapters.map(|(c, p)| {
-                let d: Content<T> = c.join("\n\n").into();
-                (p, d).into()
-            })),
+            bk.chs.insert(id, Chapter {
+                id,
+                number: page.number(),
+                src: Some(page),
+                ..Default::default()
+            });
+            added.push(id);
         }
-        //Add content type to book
-        let mut bk = Book {
+        added
+    }
+
+    /// Downloads a single content page, an image for visual media and the
+    /// text of the page otherwise
+    pub async fn content<T: Media>(&self, url: Url) -> Result<Content<T>, Error> {
+        if T::visual() {
+            // The image is requested once, with the headers the html would get
+            let page = Page::from(url);
+            self.prepare(&page)?;
+            let data: Content<T> = self.image(&page).await?.into();
+            return Ok((page, data).into());
+        }
+        let page = self.refresh(&Page::from(url)).await?;
+        let data: Content<T> = page
+            .text()
+            .ok_or_else(|| Error::Missing(format!("text on {}", page.loc)))?
+            .join("\n\n")
+            .into();
+        Ok((page, data).into())
+    }
+
+    /// Finds the index page of a book and the links to its chapters, only
+    /// the index page is loaded. See `Retriever::chapter_jobs` for the chapters.
+    pub async fn plan<T: Debug + Media + Clone>(
+        &self, page: Page,
+    ) -> Result<(Book<T>, Vec<Url>), Error> {
+        let index = self.index(&page).await?;
+        let title = index.title();
+        self.indexed(&title, &index);
+        let links = self.links(&index)?;
+        let bk: Book<T> = Book {
             title,
+            meta: index.metadata(),
             index,
             ..Default::default()
         };
-        contents.into_iter().for_each(|a: Content<T>| {
-            let place = a.src.as_ref().unwrap().get_place();
-            bk.content
-                .insert(crate::Num(place.1, Some(place.0 as u8)), a);
-        });
-        bk
+        Ok((bk, links))
+    }
+
+    /// Loads the chapter pages of `book` at `links` and finds the content
+    /// pages in them without downloading the content, so it can be queued
+    /// with `Retriever::run`. The chapters are numbered from `first`.
+    pub async fn chapter_jobs<T: Debug + Media + Clone>(
+        &self, book: &Label, first: u16, links: Vec<Url>,
+    ) -> (Vec<Chapter<T>>, Vec<Job>) {
+        let (mut chs, mut jobs) = (vec![], vec![]);
+        for page in self.pages(links).await {
+            let urls = match (T::visual(), page.get_content::<T>()) {
+                (true, Some(c)) => c
+                    .iter()
+                    .filter_map(|a| page.loc.join(a).ok())
+                    .collect::<Vec<_>>(),
+                (false, Some(_)) => vec![page.loc.to_owned()],
+                (_, None) => continue,
+            };
+            let id = first + chs.len() as u16;
+            self.found(book, id, &page, urls.len());
+            jobs.extend(
+                urls.into_iter()
+                    .enumerate()
+                    .map(|(n, a)| Job::new(book.to_owned(), id, n, a)),
+            );
+            chs.push(Chapter {
+                id,
+                number: page.number(),
+                src: Some(page),
+                ..Default::default()
+            });
+        }
+        (chs, jobs)
+    }
+
+    /// Runs `jobs` of `bk`, saving every downloaded content. Returns the
+    /// jobs and whether they succeeded, to be logged in the queue once the
+    /// book is stored.
+    pub async fn run<T: Media>(
+        &self, bk: &mut Book<T>, jobs: Vec<Job>, store: &mut Store,
+    ) -> Result<Vec<(Job, bool)>, Error> {
+        let mut done = vec![];
+        self.queued(&jobs);
+        let mut results = stream::iter(jobs.into_iter().map(|job| async move {
+            let res = self.job::<T>(&job).await;
+            (job, res)
+        }))
+        .buffered(self.jobs.get());
+        while let Some((job, res)) = results.next().await {
+            let ok = match res {
+                Ok(c) => bk.file(job.chapter, job.n, c, store)?.is_some(),
+                Err(_) => false,
+            };
+            done.push((job, ok));
+        }
+        store.flush()?;
+        Ok(done)
+    }
+
+    /// Downloads the content of `jobs`, handing each to `f` as it finishes.
+    /// Unlike `Retriever::run` it doesn't need the book, so it can be spawned
+    /// on its own task.
+    pub async fn fetch<T: Media>(
+        &self, jobs: Vec<Job>, mut f: impl FnMut(Job, Result<Content<T>, Error>),
+    ) {
+        self.queued(&jobs);
+        let mut results = stream::iter(jobs.into_iter().map(|job| async move {
+            let res = self.job::<T>(&job).await;
+            (job, res)
+        }))
+        .buffered(self.jobs.get());
+        while let Some((job, res)) = results.next().await {
+            f(job, res);
+        }
+    }
+
+    /// Downloads the content of a queued job, publishing how it went
+    async fn job<T: Media>(&self, job: &Job) -> Result<Content<T>, Error> {
+        let res = self.content::<T>(job.page.to_owned()).await;
+        self.finished(&job.book, job.chapter, &job.page, &res);
+        res
+    }
+
+    /// Receives the progress of every retrieval from now on, also those
+    /// of clones of the retriever
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<DownloadEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(
+        &self, book: &Label, chapter: Option<u16>, page: &Url, bytes: usize,
+        status: DownloadStatus,
+    ) {
+        self.events.send(DownloadEvent {
+            book: book.to_owned(),
+            chapter,
+            page: page.to_owned(),
+            bytes,
+            status,
+        })
+    }
+
+    /// Publishes that the index page of `book` was refreshed
+    fn indexed(&self, book: &Label, index: &Page) {
+        self.emit(book, None, &index.loc, index.size(), DownloadStatus::Index)
+    }
+
+    /// Publishes that chapter `ch` was found with `pages` pages of content
+    fn found(&self, book: &Label, ch: u16, page: &Page, pages: usize) {
+        let status = DownloadStatus::Chapter(pages);
+        self.emit(book, Some(ch), &page.loc, page.size(), status)
+    }
+
+    /// Publishes how many jobs of every chapter are waiting to be
+    /// downloaded. Done before the first starts, so the total is known, with
+    /// an event per chapter rather than per page so subscribers keep up.
+    fn queued(&self, jobs: &[Job]) {
+        let mut chapters = BTreeMap::new();
+        for j in jobs {
+            chapters
+                .entry((&j.book, j.chapter))
+                .or_insert((&j.page, 0))
+                .1 += 1;
+        }
+        for ((book, ch), (page, n)) in chapters {
+            self.emit(book, Some(ch), page, 0, DownloadStatus::Queued(n))
+        }
+    }
+
+    /// Publishes how downloading a page of content went
+    fn finished<T: Media>(
+        &self, book: &Label, ch: u16, page: &Url, res: &Result<Content<T>, Error>,
+    ) {
+        let (bytes, status) = match res {
+            Ok(c) => (c.data().get().len(), DownloadStatus::Done),
+            Err(e) => (0, DownloadStatus::Failed(e.to_string())),
+        };
+        self.emit(book, Some(ch), page, bytes, status)
     }
 
     /// Generate a vec with contents for every page
-    pub async fn contents<T: Media>(&self, chaps: Vec<Page>) -> Vec<Content<T>> {
-        join_all(chaps.iter().map(|page| async move {
-            self.refresh(&page).await;
-            page.get_image(&self.client).await.into()
+    pub async fn contents<T: Media>(
+        &self, chaps: Vec<Page>,
+    ) -> Vec<Result<Content<T>, Error>> {
+        stream::iter(chaps.iter().map(|page| async move {
+            self.refresh(&page).await?;
+            Ok(self.image(page).await?.into())
         }))
+        .buffered(self.jobs.get())
+        .collect()
         .await
     }
 
-    /// Gets Pages to all chapters found in an index page
-    pub async fn chapters(&self, p: &Page) -> Vec<Page> {
-        join_all(
-            p.chaps()
-                .unwrap()
-                .iter()
+    /// Gets Pages to all chapters found in an index page, skipping the ones
+    /// that couldn't be loaded
+    pub async fn chapters(&self, p: &Page) -> Result<Vec<Page>, Error> {
+        Ok(self.pages(self.links(p)?).await)
+    }
+
+    /// Absolute links to all chapters found in an index page, without
+    /// duplicates
+    pub fn links(&self, p: &Page) -> Result<Vec<Url>, Error> {
+        let mut seen = HashSet::new();
+        Ok(p.chaps()
+            .ok_or_else(|| Error::Missing(format!("chapters on {}", p.loc)))?
+            .iter()
+            .filter_map(|a| p.loc.join(a).ok())
+            .filter(|a| seen.insert(a.to_owned()))
+            .collect())
+    }
+
+    /// Loads the given urls, skipping the ones that fail
+    pub async fn pages(&self, urls: Vec<Url>) -> Vec<Page> {
+        stream::iter(
+            urls.into_iter()
                 .map(|a| async move { self.refresh(&Page::from(a)).await }),
         )
+        .buffered(self.jobs.get())
+        .filter_map(|a| async move { a.ok() })
+        .collect()
         .await
     }
 
     /// Tries getting the Index page of the work
-    pub async fn index(&self, p: &Page) -> Page {
-        self.refresh(
-            &self
-                .refresh(p)
-                .await
-                .index()
-                .map_err(|_| p.to_owned())
-                .unwrap(),
-        )
-        .await
+    pub async fn index(&self, p: &Page) -> Result<Page, Error> {
+        self.refresh(&self.refresh(p).await?.index()?).await
     }
 
     /// Initial Page download preparations and actual dl.
-    async fn dl(&self, page: &Page) -> Result<Page, Page> {
+    async fn dl(&self, page: &Page) -> Result<Page, Error> {
+        self.prepare(page)?;
+        page.refresh(Some(&self.client)).await
+    }
+
+    /// Sets up the request of a Page with the headers of its host
+    fn prepare(&self, page: &Page) -> Result<(), Error> {
         let headers = self
             .headers
-            .get(&page.domain().unwrap())
+            .get(&page.domain()?)
             .map(Headers::to_owned)
             .unwrap_or_default()
             .headers;
@@ -147,24 +461,146 @@ impl Retriever {
             self.client
                 .get(page.loc.as_str())
                 .headers(headers)
-                .build()
-                .unwrap(),
+                .build()?,
         );
-        page.refresh(Some(&self.client)).await
+        Ok(())
     }
 
-    /// Keeps track of domains being accessed and adds delay between accessed
-    async fn access(&self, p: &Page) {
-        match self.sites.lock().await.entry(p.domain().unwrap()) {
-            Occupied(mut e) => {
-                let mut b = e.get_mut().clone();
-                b.delay_if(super::duration()).await;
+    /// Runs `f` after the usual delay, retrying transient failures with
+    /// exponential backoff and skipping domains that keep failing
+    async fn retry<F, Fut, R>(&self, page: &Page, f: F) -> Result<R, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<R, Error>>, {
+        let host = page.domain()?;
+        let mut attempt = 0;
+        loop {
+            if let Some(h) = self.failures.lock().await.get(&host) {
+                if h.is_tripped() {
+                    return Err(Error::Unavailable(format!(
+                        "{} after repeated failures",
+                        host
+                    )));
+                }
+            }
+            let permits = (self.access(page).await?, self.jobs.acquire().await?);
+            let res = f().await;
+            drop(permits);
+            match res {
+                Ok(r) => {
+                    if let Some(h) = self.failures.lock().await.get_mut(&host) {
+                        h.succeed();
+                    }
+                    return Ok(r);
+                }
+                Err(e) if e.is_transient() => {
+                    let mut failures = self.failures.lock().await;
+                    let h = failures.entry(host.to_owned()).or_default();
+                    if h.fail(&self.backoff).is_tripped() ||
+                        attempt >= self.backoff.retries
+                    {
+                        return Err(e);
+                    }
+                    drop(failures);
+                    match self.backoff.wait(attempt, e.retry_after()) {
+                        Some(wait) => sleep(wait).await,
+                        None => return Err(e),
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
             }
-            Vacant(e) => {
-                e.insert(Default::default());
+        }
+    }
+
+    /// Registers a site specific Finder used for pages from `host`
+    pub fn register(
+        &mut self, host: Host, finder: impl Finder + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.finders.insert(host, Arc::new(finder));
+        self
+    }
+
+    /// Registers every site definition in `dir`, returning how many were
+    /// found and the files that were skipped with the reason
+    pub fn load_sites(
+        &mut self, dir: &Path,
+    ) -> Result<(usize, Vec<(PathBuf, Error)>), Error> {
+        let (defs, failed) = SiteDef::load_dir(dir)?;
+        for def in defs.iter() {
+            for host in def.hosts.iter() {
+                self.register(Host::parse(host)?, def.to_owned());
             }
         }
+        Ok((defs.len(), failed))
+    }
+
+    /// The Finder registered for `host`, or a built-in one
+    pub fn finder(&self, host: &Host) -> Option<SiteFinder> {
+        self.finders
+            .get(host)
+            .cloned()
+            .or_else(|| sites::builtin(host))
+    }
+
+    /// Sets how many requests may be in flight at once, across this
+    /// retriever and its clones from now on
+    pub fn set_jobs(&mut self, jobs: Jobs) -> &mut Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// How many requests may be in flight at once
+    pub fn jobs(&self) -> usize { self.jobs.get() }
+
+    /// Sets the rate limiting policy for a host, returning the previous one
+    pub fn set_limit(
+        &mut self, host: Host, limit: RateLimit,
+    ) -> Option<RateLimit> {
+        self.limits.insert(host, limit)
+    }
+
+    /// Keeps track of domains being accessed and waits until the host's
+    /// `RateLimit` allows another request. The permit has to be held for the
+    /// duration of the request. It's taken before the time slot is booked, so
+    /// requests waiting on a slow one don't all start at once when it ends.
+    async fn access(&self, p: &Page) -> Result<OwnedSemaphorePermit, Error> {
+        let host = p.domain()?;
+        let limit = self.limits.get(&host).cloned().unwrap_or_default();
+        let slots = delay(&mut *self.sites.lock().await, &host, &limit)
+            .slots
+            .clone();
+        let permit = slots
+            .acquire_owned()
+            .await
+            .map_err(|_| Error::Unavailable(host.to_string()))?;
+        let until = delay(&mut *self.sites.lock().await, &host, &limit).reserve();
         // TODO: Maybe add a trim function for the map that runs occasionally
+        match until {
+            Some(until) => sleep_until(until).await,
+            None => {
+                return Err(Error::Unavailable(format!(
+                    "{}, daily quota of {} used up",
+                    host,
+                    limit.daily.unwrap_or_default()
+                )))
+            }
+        }
+        Ok(permit)
+    }
+}
+
+/// The bookkeeping of `host`, started over if its `RateLimit` changed
+fn delay<'a>(
+    sites: &'a mut HashMap<Host, Delay>, host: &Host, limit: &RateLimit,
+) -> &'a mut Delay {
+    match sites.entry(host.to_owned()) {
+        Occupied(e) if &e.get().limit == limit => e.into_mut(),
+        Occupied(mut e) => {
+            e.insert(Delay::new(limit.to_owned()));
+            e.into_mut()
+        }
+        Vacant(e) => e.insert(Delay::new(limit.to_owned())),
     }
 }
 
@@ -172,8 +608,171 @@ impl Debug for Retriever {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Retriever")
             .field("headers", &self.headers)
+            .field("limits", &self.limits)
             .field("client", &self.client)
             .field("sites", &self.sites)
+            .field("failures", &self.failures)
+            .field("backoff", &self.backoff)
+            .field("jobs", &self.jobs)
+            .field("finders", &self.finders.keys().collect::<Vec<_>>())
+            .field("events", &self.events)
             .finish()
     }
 }
+
+#[test]
+fn retriever_is_send() {
+    fn send<T: Send + Sync>(_: &T) {}
+    fn send_future<F: Future + Send>(_: F) {}
+    let r = Retriever::default();
+    send(&r);
+    send(&Page::default());
+    send_future(r.book::<crate::Manga>(Page::default()));
+    send_future(r.book::<crate::Novel>(Page::default()));
+    send_future(r.plan::<crate::Manga>(Page::default()));
+    send_future(r.chapter_jobs::<crate::Novel>(&Label(String::new()), 0, vec![]));
+    send_future(r.fetch::<crate::Novel>(vec![], |_, _| {}));
+}
+
+#[tokio::test]
+async fn access_spacing() {
+    use std::time::Duration;
+    use tokio::time::Instant;
+    let mut r = Retriever::default();
+    let page = Page::from("https://example.com/");
+    r.set_limit(page.domain().unwrap(), RateLimit {
+        delay_ms: 100,
+        concurrent: 1,
+        ..Default::default()
+    });
+    let start = Instant::now();
+    let slow = async {
+        let _permit = r.access(&page).await.unwrap();
+        sleep(Duration::from_millis(300)).await;
+    };
+    let quick = || async {
+        r.access(&page).await.unwrap();
+        Instant::now()
+    };
+    let (_, a, b) = tokio::join!(slow, quick(), quick());
+    let (a, b) = (a.min(b), a.max(b));
+    // Waiting on the slow request doesn't use up the delay
+    assert!(a >= start + Duration::from_millis(300));
+    assert!(b >= a + Duration::from_millis(90));
+}
+
+#[tokio::test]
+async fn queued_upfront() {
+    use crate::Novel;
+    let mut r = Retriever::default();
+    r.set_jobs(Jobs::new(2));
+    let mut rx = r.subscribe();
+    // Urls without a host fail right away, without a request
+    let jobs = (0..5)
+        .map(|n| {
+            let url = format!("data:text/plain,{}", n).parse().unwrap();
+            Job::new(Label("A book".into()), 0, n, url)
+        })
+        .collect::<Vec<_>>();
+    r.fetch::<Novel>(jobs, |_, res| assert!(res.is_err())).await;
+    let mut progress = Progress::new();
+    let statuses = (0..6)
+        .map(|_| {
+            let e = rx.try_recv().unwrap();
+            progress.add(&e);
+            (e.status, progress.total)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(statuses[0].0, DownloadStatus::Queued(5));
+    assert!(statuses[1..].iter().all(|a| a.1 == 5));
+    assert!(rx.try_recv().is_err());
+    assert_eq!(progress.failed, 5);
+}
+
+#[tokio::test]
+async fn empty_chapters_skipped() {
+    let base = serve(vec![
+        (
+            "/manga/test",
+            "<html><body><div><ul><li><a href=\"/manga/test/chapter-1\">1</a>\
+             </li></ul></div></body></html>"
+                .to_string(),
+        ),
+        (
+            "/manga/test/chapter-1",
+            "<html><body><div><img src=\"/missing.jpg\"></div></body></html>"
+                .to_string(),
+        ),
+    ]);
+    let r = Retriever::default();
+    let mut bk: Book<crate::Manga> = Book {
+        index: Page::from(base.join("manga/test").unwrap().as_str()),
+        ..Default::default()
+    };
+    let u = r.update(&mut bk).await.unwrap();
+    assert_eq!(u, Update {
+        added:   vec![],
+        skipped: 1,
+    });
+    assert!(bk.chs.is_empty() && bk.content.is_empty());
+}
+
+#[test]
+fn untitled_page() {
+    // Without html, or a <title> in it, the title comes from the url
+    let page = Page::from("https://example.com/novel/the_test/");
+    assert_eq!(page.title(), Label("the test".to_string()));
+    assert_eq!(Page::from("https://example.com").title().0, "example.com");
+}
+
+#[tokio::test]
+async fn site_falls_back() {
+    let base = serve(vec![(
+        "/novel/test",
+        "<html><body><div><ul><li><a href=\"/novel/test/chapter-1\">1</a>\
+         </li></ul></div></body></html>"
+            .to_string(),
+    )]);
+    let mut r = Retriever::default();
+    let index = Page::from(base.join("novel/test").unwrap().as_str());
+    // No `#chapters` list on the page, so the heuristics find the links
+    r.register(index.domain().unwrap(), sites::RoyalRoad);
+    let index = r.refresh(&index).await.unwrap();
+    let links = r.links(&index).unwrap();
+    assert_eq!(links, vec![base.join("novel/test/chapter-1").unwrap()]);
+}
+
+/// Serves `pages` by path on a local port for the tests, anything else is a
+/// 404. Returns the address it serves at.
+#[cfg(test)]
+pub(crate) fn serve(pages: Vec<(&'static str, String)>) -> Url {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().filter_map(Result::ok) {
+            let (mut req, mut buf) = (vec![], [0; 1024]);
+            while !req.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => req.extend_from_slice(&buf[..n]),
+                }
+            }
+            let req = String::from_utf8_lossy(&req);
+            let path = req.split_whitespace().nth(1).unwrap_or_default();
+            let (status, body) = match pages.iter().find(|a| a.0 == path) {
+                Some((_, body)) => ("200 OK", body.as_str()),
+                None => ("404 Not Found", ""),
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: \
+                 {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        }
+    });
+    url.parse().unwrap()
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize as des, Serialize as ser};
+use std::time::Duration;
+use tokio::{macros::support::thread_rng_n, time::Instant};
+
+/// Retry policy for transient failures and the per-domain circuit breaker
+#[derive(Clone, Debug, ser, des)]
+pub struct Backoff {
+    /// How many times a failed request is retried
+    pub retries:     u32,
+    /// Delay before the first retry, doubled after every attempt
+    pub base_ms:     u64,
+    /// Upper bound for a single delay, also for a server's Retry-After
+    pub max_ms:      u64,
+    /// Consecutive failures after which a domain is skipped
+    pub trip_after:  u32,
+    /// How long a tripped domain is skipped for
+    pub cooldown_ms: u64,
+}
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            retries:     4,
+            base_ms:     500,
+            max_ms:      30_000,
+            trip_after:  8,
+            cooldown_ms: 120_000,
+        }
+    }
+}
+impl Backoff {
+    /// Delay before retry number `attempt`, exponential with jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_ms
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_ms);
+        let half = (exp / 2) as u32;
+        Duration::from_millis(
+            exp / 2 + thread_rng_n(half.saturating_add(1)) as u64,
+        )
+    }
+
+    /// Delay before retry number `attempt`, at least what the server asked
+    /// for with Retry-After. Nothing if it asked for more than `max_ms`, the
+    /// request is given up on then.
+    pub fn wait(
+        &self, attempt: u32, after: Option<Duration>,
+    ) -> Option<Duration> {
+        let wait = self.delay(attempt);
+        match after {
+            Some(a) if a > Duration::from_millis(self.max_ms) => None,
+            Some(a) => Some(a.max(wait)),
+            None => Some(wait),
+        }
+    }
+
+    pub fn cooldown(&self) -> Duration { Duration::from_millis(self.cooldown_ms) }
+}
+
+/// Consecutive failures seen for a domain
+#[derive(Clone, Debug, Default)]
+pub struct Failures {
+    pub count:   u32,
+    pub tripped: Option<Instant>,
+}
+impl Failures {
+    /// Whether requests to the domain should be skipped for now
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.map_or(false, |until| until > Instant::now())
+    }
+
+    pub fn fail(&mut self, policy: &Backoff) -> &mut Self {
+        self.count += 1;
+        if self.count >= policy.trip_after {
+            self.tripped = Some(Instant::now() + policy.cooldown());
+        }
+        self
+    }
+
+    pub fn succeed(&mut self) -> &mut Self {
+        self.count = 0;
+        self.tripped = None;
+        self
+    }
+}
+
+#[test]
+fn backoff_grows_and_caps() {
+    let b = Backoff::default();
+    let first = b.delay(0).as_millis() as u64;
+    assert!(first >= b.base_ms / 2 && first <= b.base_ms);
+    let third = b.delay(2).as_millis() as u64;
+    assert!(third >= b.base_ms * 2 && third <= b.base_ms * 4);
+    assert!(b.delay(30).as_millis() as u64 <= b.max_ms);
+    let minute = Some(Duration::from_secs(60));
+    assert_eq!(b.wait(0, minute), None);
+    assert_eq!(
+        b.wait(0, Some(Duration::from_secs(20))).unwrap().as_secs(),
+        20
+    );
+}
@@ -1,25 +1,67 @@
-use chrono::Duration;
-use std::{cell::RefCell, rc::Rc};
-use tokio::time::{interval_at, sleep_until, Instant, Interval};
+use crate::RateLimit;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, time::Instant};
+
+/// Per host bookkeeping for `RateLimit`
+#[derive(Clone, Debug)]
+pub struct Delay {
+    /// Theoretical arrival time of the next request
+    pub next:  Instant,
+    /// Start of the current quota day
+    pub day:   Instant,
+    pub today: u32,
+    pub slots: Arc<Semaphore>,
+    pub limit: RateLimit,
+}
 impl Default for Delay {
-    fn default() -> Self {
-        let dur = Duration::milliseconds(200).to_std().unwrap();
-        Self(
-            Instant::now(),
-            Rc::new(RefCell::new(interval_at(Instant::now() + dur, dur))),
-        )
-    }
+    fn default() -> Self { Self::new(RateLimit::default()) }
 }
-#[derive(Clone, Debug)]
-pub struct Delay(pub Instant, pub Rc<RefCell<Interval>>);
 impl Delay {
-    pub async fn delay_if(&mut self, delay: Duration) -> &mut Self {
-        let until = self.0 + delay.to_std().unwrap();
-        if until > Instant::now() {
-            sleep_until(until).await;
-            self.1.as_ref().borrow_mut().tick().await;
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            next: Instant::now(),
+            day: Instant::now(),
+            today: 0,
+            slots: Arc::new(Semaphore::new(limit.concurrent.max(1))),
+            limit,
         }
-        self.0 = Instant::now();
-        self
     }
+
+    /// Books a slot for the next request and returns when it may start,
+    /// None if the daily quota is used up
+    pub fn reserve(&mut self) -> Option<Instant> {
+        let now = Instant::now();
+        if now >= self.day + Duration::from_secs(24 * 60 * 60) {
+            self.day = now;
+            self.today = 0;
+        }
+        if self.limit.daily.map_or(false, |d| self.today >= d) {
+            return None;
+        }
+        self.today += 1;
+        let delay = self.limit.delay();
+        let next = self.next.max(now);
+        let burst = delay * self.limit.burst.saturating_sub(1);
+        self.next = next + delay;
+        Some(if next > now + burst {
+            next - burst
+        } else {
+            now
+        })
+    }
+}
+
+#[test]
+fn delay_reservations() {
+    let mut d = Delay::new(RateLimit {
+        delay_ms: 1000,
+        burst: 2,
+        daily: Some(3),
+        ..Default::default()
+    });
+    let now = Instant::now();
+    assert!(d.reserve().unwrap() <= now + Duration::from_millis(10));
+    assert!(d.reserve().unwrap() <= now + Duration::from_millis(10));
+    assert!(d.reserve().unwrap() >= now + Duration::from_millis(900));
+    assert_eq!(d.reserve(), None);
 }
@@ -0,0 +1,128 @@
+use crate::Label;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use url::Url;
+
+/// Events a subscriber can fall behind by before it misses some
+const CAPACITY: usize = 1024;
+
+/// What happened to the page of a `DownloadEvent`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DownloadStatus {
+    /// The index page of the book was refreshed
+    Index,
+    /// A chapter was found with this many pages of content
+    Chapter(usize),
+    /// This many pages of content of the chapter are waiting to be
+    /// downloaded, sent for all chapters of a download before any starts
+    Queued(usize),
+    /// A page of content was downloaded
+    Done,
+    /// A page of content couldn't be downloaded
+    Failed(String),
+}
+
+/// Progress of the retrieval of a book, see `Retriever::subscribe`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadEvent {
+    pub book:    Label,
+    pub chapter: Option<u16>,
+    pub page:    Url,
+    /// Size of what was downloaded, 0 if nothing was
+    pub bytes:   usize,
+    pub status:  DownloadStatus,
+}
+
+/// Publishes download events to every subscriber, clones publish to the same
+/// subscribers
+#[derive(Clone, Debug)]
+pub struct Events(Sender<DownloadEvent>);
+impl Events {
+    pub fn subscribe(&self) -> Receiver<DownloadEvent> { self.0.subscribe() }
+
+    /// Publishes `e`, it's dropped if nobody is subscribed
+    pub fn send(&self, e: DownloadEvent) { self.0.send(e).ok(); }
+}
+impl Default for Events {
+    fn default() -> Self { Self(broadcast::channel(CAPACITY).0) }
+}
+
+/// Running totals of download events, for showing progress and estimating
+/// how long the rest takes
+#[derive(Clone, Debug)]
+pub struct Progress {
+    /// Pages of content queued
+    pub total:  usize,
+    pub done:   usize,
+    pub failed: usize,
+    pub bytes:  u64,
+    start:      Instant,
+}
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            total:  0,
+            done:   0,
+            failed: 0,
+            bytes:  0,
+            start:  Instant::now(),
+        }
+    }
+
+    pub fn add(&mut self, e: &DownloadEvent) {
+        self.bytes += e.bytes as u64;
+        match e.status {
+            DownloadStatus::Queued(n) => self.total += n,
+            DownloadStatus::Done => self.done += 1,
+            DownloadStatus::Failed(_) => self.failed += 1,
+            DownloadStatus::Index | DownloadStatus::Chapter(_) => {}
+        }
+    }
+
+    /// Pages of content that haven't finished yet
+    pub fn remaining(&self) -> usize {
+        self.total.saturating_sub(self.done + self.failed)
+    }
+
+    /// Time left at the rate pages finished so far, `None` until one has
+    pub fn eta(&self) -> Option<Duration> { self.eta_after(self.start.elapsed()) }
+
+    fn eta_after(&self, elapsed: Duration) -> Option<Duration> {
+        match self.done + self.failed {
+            0 => None,
+            finished => {
+                Some(elapsed.mul_f64(self.remaining() as f64 / finished as f64))
+            }
+        }
+    }
+}
+impl Default for Progress {
+    fn default() -> Self { Self::new() }
+}
+
+#[test]
+fn progress_totals() {
+    let event = |status| DownloadEvent {
+        book: Label("A book".into()),
+        chapter: Some(0),
+        page: "https://example.com/1.jpg".parse().unwrap(),
+        bytes: 100,
+        status,
+    };
+    let mut p = Progress::new();
+    p.add(&event(DownloadStatus::Queued(4)));
+    assert_eq!(p.eta_after(Duration::from_secs(10)), None);
+    p.add(&event(DownloadStatus::Done));
+    p.add(&event(DownloadStatus::Failed("404".into())));
+    assert_eq!((p.total, p.done, p.failed, p.remaining()), (4, 1, 1, 2));
+    assert_eq!(p.bytes, 300);
+    assert_eq!(
+        p.eta_after(Duration::from_secs(10)),
+        Some(Duration::from_secs(10))
+    );
+
+    let events = Events::default();
+    let mut rx = events.subscribe();
+    events.clone().send(event(DownloadStatus::Done));
+    assert_eq!(rx.try_recv().unwrap().status, DownloadStatus::Done);
+}
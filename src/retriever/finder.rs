@@ -1,31 +1,39 @@
-use crate::Label;
+use crate::{Label, Metadata, Num};
 use select::{
     document::Document,
     predicate::{Child, Descendant, Name, Or, Text},
 };
 #[cfg(feature = "trait_ojb_ser")]
 use serde_traitobject::{Deserialize as des, Serialize as ser};
-use std::cell::Ref;
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+use url::Url;
 
-pub type Doc<'a> = Ref<'a, Option<Document>>;
+pub type Doc = Option<Document>;
+pub type Meta = BTreeMap<String, String>;
+pub type SiteFinder = Arc<dyn Finder + Send + Sync>;
 type Cnt = Option<Vec<String>>;
 
+/// Site specific finders have to be serializable with `trait_ojb_ser`
 #[cfg(feature = "trait_ojb_ser")]
-pub trait Finder: des + ser {
+pub trait FinderBase: des + ser {}
+#[cfg(feature = "trait_ojb_ser")]
+impl<T: des + ser> FinderBase for T {}
+#[cfg(not(feature = "trait_ojb_ser"))]
+pub trait FinderBase {}
+#[cfg(not(feature = "trait_ojb_ser"))]
+impl<T> FinderBase for T {}
+
+pub trait Finder: FinderBase {
     /// Returns the text from the children of the <div> with most <p> tags
     #[inline]
     fn text_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
         Box::new(|doc: Doc| {
-            doc.as_ref().map(|a| {
+            doc.as_ref().and_then(|a| {
                 // TODO: Improve by par_map()?
                 a.select(Child(Name("div"), Name("p")))
-                    .map(|a| a.parent().unwrap().children().into_selection())
+                    .filter_map(|a| Some(a.parent()?.children().into_selection()))
                     .max_by(|a, b| a.len().cmp(&b.len()))
-                    .unwrap()
-                    .select(Text)
-                    .iter()
-                    .map(|a| a.text())
-                    .collect()
+                    .map(|s| s.select(Text).iter().map(|a| a.text()).collect())
             })
         })
     }
@@ -33,16 +41,18 @@ pub trait Finder: des + ser {
     #[inline]
     fn images_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
         Box::new(|doc: Doc| {
-            doc.as_ref().map(|a| {
+            doc.as_ref().and_then(|a| {
                 a.select(Child(Name("div"), Name("img")))
-                    .map(|a| {
-                        a.parent().unwrap().select(Name("img")).into_selection()
+                    .filter_map(|a| {
+                        Some(a.parent()?.select(Name("img")).into_selection())
                     })
                     .max_by(|a, b| a.len().cmp(&b.len()))
-                    .unwrap()
-                    .iter()
-                    .map(|a| a.attr("src").unwrap().to_string())
-                    .collect()
+                    .map(|s| {
+                        s.iter()
+                            .filter_map(|a| a.attr("src"))
+                            .map(|a| a.to_string())
+                            .collect()
+                    })
                 /* TODO: Similar to index() add a check for links similarity */
             })
         })
@@ -51,47 +61,33 @@ pub trait Finder: des + ser {
     #[inline]
     fn chaps_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
         Box::new(|doc: Doc| {
-            doc.as_ref().map(|a: &Document| {
+            doc.as_ref().and_then(|a: &Document| {
                 a.select(Descendant(
                     Name("div"),
                     Or(Name("p"), Or(Name("table"), Name("ul"))),
                 ))
                 .map(|a| a.select(Name("a")).into_selection())
                 .max_by(|a, b| a.len().cmp(&b.len()))
-                .unwrap()
-                .iter()
-                .filter_map(|a| a.attr("href"))
-                .map(|a| a.to_string())
-                .collect()
+                .map(|s| {
+                    s.iter()
+                        .filter_map(|a| a.attr("href"))
+                        .map(|a| a.to_string())
+                        .collect()
+                })
             })
             /* TODO: Add a similarity check and only return the biggest cluster of
             similar links */
         })
     }
-    /// Returns something that looks like a book title
+    /// Returns something that looks like a book title, if there is a <title>
     #[inline]
-    fn title_def(&self) -> Box<dyn Fn(Doc) -> Label> {
+    fn title_def(&self) -> Box<dyn Fn(Doc) -> Option<Label>> {
         Box::new(|doc: Doc| {
-            let title = doc
-                .as_ref()
-                .expect("HTML not found.")
-                .select(Name("title"))
-                .into_selection()
-                .first()
-                .unwrap()
-                .text();
-            if title.contains(" Chapter") {
-                title
-                    .split(" Chapter")
-                    .filter(|&a| a != "")
-                    .collect::<Vec<_>>()
-                    .first()
-                    .unwrap()
-                    .to_string()
-            } else {
-                title
-            }
-            .into()
+            let title = doc.as_ref()?.select(Name("title")).next()?.text();
+            title
+                .split(" Chapter")
+                .find(|a| !a.trim().is_empty())
+                .map(|a| a.to_string().into())
             // .to_ascii_lowercase()
             // .split(" chapter")
             // .filter(|&a| a != "")
@@ -110,108 +106,71 @@ pub trait Finder: des + ser {
             // .into()})
         })
     }
-}
-#[cfg(not(feature = "trait_ojb_ser"))]
-pub trait Finder {
-    /// Returns the text from the children of the <div> with most <p> tags
+    /// Returns the href of a link that looks like it leads to the next page
     #[inline]
-    fn text_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+    fn next_def(&self) -> Box<dyn Fn(Doc) -> Option<String>> {
         Box::new(|doc: Doc| {
-            doc.as_ref().map(|a| {
-                // TODO: Improve by par_map()?
-                a.select(Child(Name("div"), Name("p")))
-                    .map(|a| a.parent().unwrap().children().into_selection())
-                    .max_by(|a, b| a.len().cmp(&b.len()))
-                    .unwrap()
-                    .select(Text)
-                    .iter()
-                    .map(|a| a.text())
-                    .collect()
+            doc.as_ref().and_then(|a| {
+                a.select(Name("a"))
+                    .find(|a| a.text().to_lowercase().contains("next"))
+                    .and_then(|a| a.attr("href"))
+                    .map(|a| a.to_string())
             })
         })
     }
-    /// similar to index() return the srcs from the div with most <img>
+    /// Returns the chapter number encoded in the url, if the site does that
     #[inline]
-    fn images_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+    fn number_def(&self) -> Box<dyn Fn(&Url) -> Option<Num>> {
+        Box::new(|_: &Url| None)
+    }
+    /// Returns the OpenGraph properties of the page, e.g. `og:description`
+    #[inline]
+    fn meta_def(&self) -> Box<dyn Fn(Doc) -> Meta> {
         Box::new(|doc: Doc| {
-            doc.as_ref().map(|a| {
-                a.select(Child(Name("div"), Name("img")))
-                    .map(|a| {
-                        a.parent().unwrap().select(Name("img")).into_selection()
+            doc.as_ref().map_or_else(Meta::new, |a| {
+                a.select(Name("meta"))
+                    .filter_map(|a| {
+                        Some((
+                            a.attr("property")?.to_string(),
+                            a.attr("content")?.to_string(),
+                        ))
                     })
-                    .max_by(|a, b| a.len().cmp(&b.len()))
-                    .unwrap()
-                    .iter()
-                    .map(|a| a.attr("src").unwrap().to_string())
                     .collect()
-                /* TODO: Similar to index() add a check for links similarity */
             })
         })
     }
-    /// Returns the biggest congregation of links in the html
+    /// Returns the metadata of a book from its index page at `base`, built
+    /// from what `meta_def` extracts
     #[inline]
-    fn chaps_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
-        Box::new(|doc: Doc| {
-            doc.as_ref().map(|a: &Document| {
-                a.select(Descendant(
-                    Name("div"),
-                    Or(Name("p"), Or(Name("table"), Name("ul"))),
-                ))
-                .map(|a| a.select(Name("a")).into_selection())
-                .max_by(|a, b| a.len().cmp(&b.len()))
-                .unwrap()
-                .iter()
-                .filter_map(|a| a.attr("href"))
-                .map(|a| a.to_string())
-                .collect()
-            })
-            /* TODO: Add a similarity check and only return the biggest cluster of
-            similar links */
+    fn metadata_def(&self) -> Box<dyn Fn(Doc, &Url) -> Metadata> {
+        let meta = self.meta_def();
+        Box::new(move |doc: Doc, base: &Url| {
+            Metadata::from_meta(&meta(doc), base)
         })
     }
-    /// Returns something that looks like a book title
-    #[inline]
-    fn title_def(&self) -> Box<dyn Fn(Doc) -> Label> {
-        Box::new(|doc: Doc| {
-            let title = doc
-                .as_ref()
-                .expect("HTML not found.")
-                .select(Name("title"))
-                .into_selection()
-                .first()
-                .unwrap()
-                .text();
-            if title.contains(" Chapter") {
-                title
-                    .split(" Chapter")
-                    .filter(|&a| a != "")
-                    .collect::<Vec<_>>()
-                    .first()
-                    .unwrap()
-                    .to_string()
-            } else {
-                title
-            }
-            .into()
-            // .to_ascii_lowercase()
-            // .split(" chapter")
-            // .filter(|&a| a != "")
-            // .collect::<Vec<_>>()
-            // .first()
-            // .unwrap()
-            // .chars()
-            // .fold(String::new(), |mut acc, s| {
-            //     if acc.is_empty() || "- ".contains(acc.chars().last().unwrap())
-            // {         acc.extend(s.to_uppercase());
-            //     } else {
-            //         acc.push(s);
-            //     }
-            //     acc
-            // })
-            // .into()})
-        })
+}
+
+/// The generic `Finder`, used for sites without a specific one
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(
+    feature = "trait_ojb_ser",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Heuristics;
+impl Finder for Heuristics {}
+
+/// Holds the site specific `Finder` a Page was retrieved with
+#[derive(Clone, Default)]
+pub struct FinderSlot(pub Option<SiteFinder>);
+impl Debug for FinderSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Some(Finder)"),
+            None => f.write_str("None"),
+        }
     }
 }
+
 pub trait Get: Finder {
     fn doc(&self) -> Doc;
     #[inline]
@@ -221,5 +180,9 @@ pub trait Get: Finder {
     #[inline]
     fn images(&self) -> Cnt { self.images_def()(self.doc()) }
     #[inline]
-    fn title(&self) -> Label { self.title_def()(self.doc()) }
+    fn title(&self) -> Label { self.title_def()(self.doc()).unwrap_or_default() }
+    #[inline]
+    fn next_link(&self) -> Option<String> { self.next_def()(self.doc()) }
+    #[inline]
+    fn meta(&self) -> Meta { self.meta_def()(self.doc()) }
 }
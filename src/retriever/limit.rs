@@ -0,0 +1,106 @@
+use crate::{duration, Error};
+use serde::{Deserialize as des, Serialize as ser};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How hard a single host may be hit
+#[derive(Clone, Debug, PartialEq, Eq, ser, des)]
+pub struct RateLimit {
+    /// Minimum time between two requests once the burst is used up
+    pub delay_ms:   u64,
+    /// Requests allowed to be in flight at the same time
+    pub concurrent: usize,
+    /// Requests allowed back to back before the delay kicks in
+    pub burst:      u32,
+    /// Requests allowed per day, unlimited if None
+    pub daily:      Option<u32>,
+}
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            delay_ms:   duration().num_milliseconds() as u64,
+            concurrent: 2,
+            burst:      1,
+            daily:      None,
+        }
+    }
+}
+impl RateLimit {
+    /// Gentle policy for small sites
+    pub fn gentle() -> Self {
+        Self {
+            delay_ms: 1500,
+            concurrent: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Fast policy for CDNs serving images
+    pub fn cdn() -> Self {
+        Self {
+            delay_ms: 50,
+            concurrent: 8,
+            burst: 16,
+            ..Default::default()
+        }
+    }
+
+    pub fn delay(&self) -> Duration { Duration::from_millis(self.delay_ms) }
+}
+
+/// Upper bound of requests in flight across all hosts. Clones of a
+/// `Retriever` share the slots, so downloads running side by side don't add
+/// up. Per host limits come from `RateLimit::concurrent`.
+#[derive(Clone, Debug, ser, des)]
+#[serde(from = "usize", into = "usize")]
+pub struct Jobs {
+    max:   usize,
+    slots: Arc<Semaphore>,
+}
+impl Default for Jobs {
+    fn default() -> Self { Self::new(16) }
+}
+impl Jobs {
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            slots: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    pub fn get(&self) -> usize { self.max }
+
+    /// Waits for a free slot, which has to be held for the duration of the
+    /// request
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, Error> {
+        self.slots
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| Error::Unavailable("request slots".into()))
+    }
+}
+impl PartialEq for Jobs {
+    fn eq(&self, other: &Self) -> bool { self.max == other.max }
+}
+impl Eq for Jobs {}
+impl From<usize> for Jobs {
+    fn from(max: usize) -> Self { Self::new(max) }
+}
+impl From<Jobs> for usize {
+    fn from(jobs: Jobs) -> Self { jobs.max }
+}
+
+#[tokio::test]
+async fn jobs_shared() {
+    let jobs = Jobs::new(2);
+    let other = jobs.clone();
+    let _a = jobs.acquire().await.unwrap();
+    let _b = other.acquire().await.unwrap();
+    assert_eq!(jobs.slots.available_permits(), 0);
+    drop(_a);
+    assert_eq!(other.slots.available_permits(), 1);
+    let loaded: Jobs = serde_json::from_str("4").unwrap();
+    assert_eq!((loaded.get(), loaded.slots.available_permits()), (4, 4));
+}
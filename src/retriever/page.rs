@@ -1,17 +1,31 @@
-use crate::{Finder, Get};
-use chrono::{DateTime, Duration, Utc};
-use reqwest::{Client, Request, Url};
-use select::{
-    document::Document,
-    predicate::{Child, Name, Text},
+use crate::{
+    Doc,
+    Error,
+    Finder,
+    FinderSlot,
+    Format,
+    Get,
+    Heuristics,
+    Label,
+    Meta,
+    Metadata,
+    Num,
+    SiteFinder,
 };
+use chrono::{DateTime, Duration, Utc};
+use reqwest::{header::CONTENT_TYPE, Client, Request, Url};
+use select::document::Document;
 use serde::{Deserialize as des, Serialize as ser};
 use std::{
-    cell::{Cell, RefCell},
     cmp::Ordering,
     hash::{Hash, Hasher},
-    rc::Rc,
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc,
+        Mutex,
+        RwLock,
+    },
 };
 use url::Host;
 
@@ -21,94 +35,148 @@ impl Default for Page {
             loc:  "http://codenova.ddns.net/".parse().unwrap(),
             last: Utc::now(),
             html: Default::default(),
-            doc:  Default::default(),
             req:  Default::default(),
             full: Default::default(),
+            site: Default::default(),
         }
     }
 }
 
-#[derive(Clone, Debug, ser, des)]
+/// A web page, its cached html and the request used to fetch it. Safe to share
+/// between threads; the html is parsed into a `Document` on demand.
+#[derive(Debug, ser, des)]
 pub struct Page {
     pub loc:  Url,
     last:     DateTime<Utc>,
     #[serde(skip)]
-    html:     RefCell<Option<String>>,
+    html:     RwLock<Option<String>>,
     #[serde(skip)]
-    pub doc:  RefCell<Option<Document>>,
+    req:      Arc<Mutex<Option<Request>>>,
     #[serde(skip)]
-    req:      Rc<RefCell<Option<Request>>>,
-    pub full: Cell<bool>,
+    pub full: AtomicBool,
+    #[serde(skip)]
+    site:     RwLock<FinderSlot>,
 }
 
 impl Page {
     /// Loads the html and parsed html in Page preparation for future actions
-    pub async fn refresh(&self, client: Option<&Client>) -> Result<Self, Self> {
-        if self.doc.borrow().is_none() {
-            self.full.set(false);
+    pub async fn refresh(&self, client: Option<&Client>) -> Result<Self, Error> {
+        if self.html.read().unwrap().is_none() {
+            self.full.store(false, Relaxed);
         };
-        if let Some(rq) = self.req.borrow().as_ref() {
-            // TODO: Better error recovery with a failures counter in Retriever
-            let resp = client
-                .unwrap_or(&Client::new())
-                .execute(rq.try_clone().unwrap())
-                .await
-                .unwrap();
-            // dbg!(&self);
-            let html = resp.text().await.unwrap();
-            self.doc.replace(Some(html.as_str().into()));
-            self.html.replace(Some(html));
-            self.full.set(true);
-            Ok(self.to_owned())
-        } else {
-            Err(self.to_owned())
-        }
+        let rq = self
+            .cloned_request()
+            .ok_or_else(|| Error::Missing(format!("request for {}", self.loc)))?;
+        let resp = client.unwrap_or(&Client::new()).execute(rq).await?;
+        let html = Error::check(resp)?.text().await?;
+        *self.html.write().unwrap() = Some(html);
+        self.full.store(true, Relaxed);
+        Ok(self.to_owned())
     }
 
     pub fn request(&self, re: Request) -> &Self {
-        self.req.replace(Some(re));
-        self.full.set(false);
+        *self.req.lock().unwrap() = Some(re);
+        self.full.store(false, Relaxed);
+        self
+    }
+
+    /// Makes the Page use a site specific Finder instead of the heuristics
+    pub fn set_finder(&self, finder: SiteFinder) -> &Self {
+        self.site.write().unwrap().0 = Some(finder);
         self
     }
 
+    /// The Finder used to extract content from the Page
+    pub fn finder(&self) -> SiteFinder {
+        self.site
+            .read()
+            .unwrap()
+            .0
+            .clone()
+            .unwrap_or_else(|| Arc::new(Heuristics))
+    }
+
+    /// `def` of the site specific Finder, falling back to the heuristics
+    /// where it finds nothing
+    fn or_heuristics(
+        &self,
+        def: impl Fn(&dyn Finder) -> Box<dyn Fn(Doc) -> Option<Vec<String>>>,
+    ) -> Box<dyn Fn(Doc) -> Option<Vec<String>>> {
+        match self.site.read().unwrap().0.clone() {
+            Some(site) => {
+                let (site, heuristics) = (def(&*site), def(&Heuristics));
+                Box::new(move |doc: Doc| {
+                    site(doc.clone()).or_else(|| heuristics(doc))
+                })
+            }
+            None => def(&Heuristics),
+        }
+    }
+
+    /// Chapter number the site encodes in the url, if it does
+    pub fn number(&self) -> Option<Num> { self.number_def()(&self.loc) }
+
+    /// Metadata of the book, if the Page is its index
+    pub fn metadata(&self) -> Metadata {
+        self.metadata_def()(self.doc(), &self.loc)
+    }
+
+    /// Whether the html has been loaded
+    pub fn is_full(&self) -> bool { self.full.load(Relaxed) }
+
+    /// Whether a request was prepared for the Page, pages read back from the
+    /// manifest have none
+    pub fn has_request(&self) -> bool { self.req.lock().unwrap().is_some() }
+
+    /// Size of the loaded html in bytes
+    pub fn size(&self) -> usize {
+        self.html.read().unwrap().as_ref().map_or(0, String::len)
+    }
+
+    fn cloned_request(&self) -> Option<Request> {
+        self.req
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(Request::try_clone)
+    }
+
     /// Get the `example.com` from `http://example.com/path/`
     /// would fail for http://localhost/path
-    pub fn domain(&self) -> Result<Host, String> {
+    pub fn domain(&self) -> Result<Host, Error> {
         match self.loc.host() {
             Some(d) => Ok(d.to_owned()),
             // for treating IPs differently
             // Some(d @ Host::Ipv4(_)) => Ok(d.to_owned()),
             // Some(d @ Host::Ipv6(_)) => Ok(d.to_owned()),
-            _ => Err("No host.".to_owned()),
+            _ => Err(Error::UnknownSite(self.loc.to_string())),
         }
     }
 
-    pub async fn next(&self, client: &Client, pred: &str) -> Option<Page> {
-        let s = self.doc.borrow().as_ref().and_then(|a| {
-            a.select(Child(Name("a"), Text))
-                .filter(|a| a.text().contains(pred))
-                .map(|a| {
-                    Page::from(
-                        a.parent().unwrap().attr("href").unwrap().to_string(),
-                    )
-                })
-                .next()
-        });
+    /// Loads the page the Finder's next link leads to, if there is one
+    pub async fn next(&self, client: &Client) -> Result<Option<Page>, Error> {
+        let s = self
+            .next_link()
+            .and_then(|a| self.loc.join(&a).ok())
+            .map(|a| Page::from(a.as_str()));
         match s {
-            Some(s) => Some(s.refresh(Some(client)).await.unwrap().to_owned()),
-            None => None,
+            Some(s) => {
+                s.request(client.get(s.loc.as_str()).build()?);
+                Ok(Some(s.refresh(Some(client)).await?))
+            }
+            None => Ok(None),
         }
     }
 
     /// Returns a Page leading the the index page of the chapter
-    pub fn index(&self) -> Result<Self, url::ParseError> {
+    pub fn index(&self) -> Result<Self, Error> {
         // TODO: Alternatively, find links up or left from other links leading to
         // the current page
         let base = self.loc.origin().ascii_serialization();
         let mut index = self
             .loc
             .path_segments()
-            .unwrap()
+            .ok_or_else(|| Error::Parse(format!("path of {}", self.loc)))?
             .rev()
             .fold((Vec::new(), 0, 0), |mut acc, s| {
                 if s.to_lowercase().contains("chapter") {
@@ -130,16 +198,15 @@ impl Page {
             .collect::<Vec<_>>()
             .join("/")
             .parse()
+            .map_err(Error::from)
     }
 
     pub fn get_place(&self) -> (u16, u16, String) {
         let segments = self
             .loc
             .path_segments()
-            .unwrap()
-            .rev()
-            .filter(|&a| a != "")
-            .collect::<Vec<_>>();
+            .map(|s| s.rev().filter(|&a| a != "").collect::<Vec<_>>())
+            .unwrap_or_default();
         let numbers = segments
             .iter()
             .map(|a| {
@@ -173,18 +240,22 @@ impl Page {
         }
     }
 
-    pub async fn get_image(&self, client: &Client) -> Vec<u8> {
-        match self.req.borrow().as_ref() {
-            Some(req) => client
-                .execute(req.try_clone().unwrap())
-                .await
-                .unwrap()
-                .bytes()
-                .await
-                .unwrap()
-                .to_vec(),
-            None => Vec::new(),
-        }
+    /// Downloads the raw bytes behind the Page, using the prepared request if
+    /// there is one, along with the image format from the Content-Type
+    pub async fn get_image(
+        &self, client: &Client,
+    ) -> Result<(Vec<u8>, Option<Format>), Error> {
+        let req = match self.cloned_request() {
+            Some(req) => req,
+            None => client.get(self.loc.as_str()).build()?,
+        };
+        let resp = Error::check(client.execute(req).await?)?;
+        let format = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|a| a.to_str().ok())
+            .and_then(Format::from_mime);
+        Ok((resp.bytes().await?.to_vec(), format))
     }
 
     pub fn check_visual(&self) -> Option<bool> {
@@ -193,65 +264,118 @@ impl Page {
         let f = |s: &&str| -> bool {
             self.loc.origin().ascii_serialization().contains(s)
         };
+        let short = || self.text().map_or(true, |t| t.len() < 20);
         Some(match (t.iter().any(|s| f(s)), p.iter().any(|s| f(s))) {
-            (true, true) => self.text().unwrap().len() < 20,
+            (true, true) => short(),
             (true, false) => false,
             (false, true) => true,
-            (false, false) => self.text().unwrap().len() < 20,
+            (false, false) => short(),
         })
     }
 
     pub fn is_old(&self, d: Option<Duration>) -> bool {
         (self.last + d.unwrap_or(Duration::seconds(10))) < Utc::now() ||
-            !self.full.get()
+            !self.is_full()
     }
 
     pub fn empty(&self) {
-        self.html.replace(Default::default());
-        self.doc.replace(Default::default());
-        self.full.set(false);
+        *self.html.write().unwrap() = None;
+        self.full.store(false, Relaxed);
     }
 }
 
-impl Finder for Page {}
+impl Finder for Page {
+    fn text_def(&self) -> Box<dyn Fn(Doc) -> Option<Vec<String>>> {
+        self.or_heuristics(|f| f.text_def())
+    }
+
+    fn images_def(&self) -> Box<dyn Fn(Doc) -> Option<Vec<String>>> {
+        self.or_heuristics(|f| f.images_def())
+    }
+
+    fn chaps_def(&self) -> Box<dyn Fn(Doc) -> Option<Vec<String>>> {
+        self.or_heuristics(|f| f.chaps_def())
+    }
+
+    fn title_def(&self) -> Box<dyn Fn(Doc) -> Option<Label>> {
+        self.finder().title_def()
+    }
+
+    fn next_def(&self) -> Box<dyn Fn(Doc) -> Option<String>> {
+        self.finder().next_def()
+    }
+
+    fn meta_def(&self) -> Box<dyn Fn(Doc) -> Meta> { self.finder().meta_def() }
+
+    fn metadata_def(&self) -> Box<dyn Fn(Doc, &Url) -> Metadata> {
+        self.finder().metadata_def()
+    }
+
+    fn number_def(&self) -> Box<dyn Fn(&Url) -> Option<Num>> {
+        self.finder().number_def()
+    }
+}
 impl Get for Page {
     #[inline]
-    fn doc(&self) -> crate::Doc { self.doc.borrow() }
+    fn doc(&self) -> crate::Doc {
+        self.html.read().unwrap().as_deref().map(Document::from)
+    }
+
+    /// The title the Finder finds, else the last part of the url
+    fn title(&self) -> Label {
+        self.title_def()(self.doc()).unwrap_or_else(|| {
+            let name = self
+                .loc
+                .path_segments()
+                .and_then(|s| s.filter(|a| !a.is_empty()).last())
+                .or_else(|| self.loc.host_str())
+                .unwrap_or_default();
+            name.replace(|c| c == '-' || c == '_', " ").into()
+        })
+    }
 }
 
 impl Eq for Page {}
 impl PartialEq for Page {
     fn eq(&self, other: &Self) -> bool {
-        self.full == other.full &&
+        self.is_full() == other.is_full() &&
             self.loc == other.loc &&
-            self.html == other.html &&
+            *self.html.read().unwrap() == *other.html.read().unwrap() &&
             self.last == other.last
     }
 }
 impl Ord for Page {
     fn cmp(&self, other: &Self) -> Ordering {
-        (&self.loc, &self.full, &self.last).cmp(&(
+        (&self.loc, self.is_full(), &self.last).cmp(&(
             &other.loc,
-            &other.full,
+            other.is_full(),
             &other.last,
         ))
     }
 }
 impl PartialOrd for Page {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some((&self.loc, &self.full, &self.last).cmp(&(
-            &other.loc,
-            &other.full,
-            &other.last,
-        )))
+        Some(self.cmp(other))
     }
 }
 impl Hash for Page {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.loc.hash(state);
         self.last.hash(state);
-        (*self.html.borrow()).hash(state);
-        self.full.get().hash(state);
+        (*self.html.read().unwrap()).hash(state);
+        self.is_full().hash(state);
+    }
+}
+impl Clone for Page {
+    fn clone(&self) -> Self {
+        Self {
+            loc:  self.loc.clone(),
+            last: self.last,
+            html: RwLock::new(self.html.read().unwrap().clone()),
+            req:  self.req.clone(),
+            full: AtomicBool::new(self.is_full()),
+            site: RwLock::new(self.site.read().unwrap().clone()),
+        }
     }
 }
 impl<T: Into<String>> From<T> for Page {
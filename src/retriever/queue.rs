@@ -0,0 +1,228 @@
+use crate::{Error, Label};
+use serde::{Deserialize as des, Serialize as ser};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, ErrorKind::NotFound, Write},
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+/// Attempts after which a job is given up on
+pub const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ser, des)]
+pub enum Status {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// A single content page waiting to be downloaded
+#[derive(Clone, Debug, PartialEq, Eq, ser, des)]
+pub struct Job {
+    pub book:     Label,
+    pub chapter:  u16,
+    /// Place of the page in the chapter
+    pub n:        usize,
+    pub page:     Url,
+    pub status:   Status,
+    pub attempts: u32,
+}
+impl Job {
+    pub fn new(book: Label, chapter: u16, n: usize, page: Url) -> Self {
+        Self {
+            book,
+            chapter,
+            n,
+            page,
+            status: Status::Pending,
+            attempts: 0,
+        }
+    }
+}
+
+/// Download jobs persisted to disk so interrupted downloads can resume.
+/// Changes are appended to a log next to the queue file as they happen,
+/// `Queue::store` folds them into the queue file.
+#[derive(Debug, Default, ser, des)]
+pub struct Queue {
+    #[serde(skip)]
+    path:     PathBuf,
+    pub jobs: Vec<Job>,
+    #[serde(skip)]
+    log:      Option<File>,
+}
+impl Queue {
+    /// Opens the queue stored at `path` and replays its log, or an empty one
+    /// if there is none
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut q: Self = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == NotFound => Default::default(),
+            Err(e) => return Err(e.into()),
+        };
+        q.path = path.to_owned();
+        match fs::read_to_string(q.log_path()) {
+            // A line cut off by a crash is skipped, its job just runs again
+            Ok(log) => log
+                .lines()
+                .filter_map(|a| serde_json::from_str::<Job>(a).ok())
+                .for_each(|a| q.replay(a)),
+            Err(e) if e.kind() == NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(q)
+    }
+
+    fn log_path(&self) -> PathBuf { self.path.with_extension("log") }
+
+    /// Applies a logged change: adds or updates the job, dropping it once
+    /// it's done
+    fn replay(&mut self, job: Job) {
+        let i = self
+            .jobs
+            .iter()
+            .position(|j| j.book == job.book && j.page == job.page);
+        match (i, job.status) {
+            (Some(i), Status::Done) => {
+                self.jobs.remove(i);
+            }
+            (Some(i), _) => self.jobs[i] = job,
+            (None, Status::Done) => {}
+            (None, _) => self.jobs.push(job),
+        }
+    }
+
+    /// Appends changed jobs to the log, if the queue was opened from a file
+    fn append<'a>(
+        &mut self, jobs: impl IntoIterator<Item = &'a Job>,
+    ) -> Result<(), Error> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let path = self.log_path();
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let log =
+                    OpenOptions::new().create(true).append(true).open(path)?;
+                self.log.insert(log)
+            }
+        };
+        let mut w = BufWriter::new(log);
+        for job in jobs {
+            serde_json::to_writer(&mut w, job)?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Writes the queue back to where it was opened from and empties the log
+    pub fn store(&mut self) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut w, self)?;
+        w.flush()?;
+        fs::rename(tmp, &self.path)?;
+        self.log = None;
+        match fs::remove_file(self.log_path()) {
+            Err(e) if e.kind() != NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds jobs that aren't queued yet
+    pub fn extend(
+        &mut self, jobs: impl IntoIterator<Item = Job>,
+    ) -> Result<(), Error> {
+        let mut queued = self
+            .jobs
+            .iter()
+            .map(|j| (j.book.to_owned(), j.page.to_owned()))
+            .collect::<HashSet<_>>();
+        let new = jobs
+            .into_iter()
+            .filter(|j| queued.insert((j.book.to_owned(), j.page.to_owned())))
+            .collect::<Vec<_>>();
+        self.append(&new)?;
+        self.jobs.extend(new);
+        Ok(())
+    }
+
+    /// Jobs of `book` that still have to run
+    pub fn pending(&self, book: &Label) -> Vec<Job> {
+        self.jobs
+            .iter()
+            .filter(|j| &j.book == book && j.status == Status::Pending)
+            .cloned()
+            .collect()
+    }
+
+    /// Books with jobs that still have to run
+    pub fn books(&self) -> Vec<Label> {
+        let mut books = self
+            .jobs
+            .iter()
+            .filter(|j| j.status == Status::Pending)
+            .map(|j| j.book.to_owned())
+            .collect::<Vec<_>>();
+        books.sort();
+        books.dedup();
+        books
+    }
+
+    /// Records the outcome of a job in the log, dropping it if it's done
+    pub fn finish(&mut self, job: &Job, ok: bool) -> Result<(), Error> {
+        let mut j = match self
+            .jobs
+            .iter()
+            .find(|j| j.book == job.book && j.page == job.page)
+        {
+            Some(j) => j.to_owned(),
+            None => return Ok(()),
+        };
+        j.attempts += 1;
+        j.status = match (ok, j.attempts >= MAX_ATTEMPTS) {
+            (true, _) => Status::Done,
+            (false, true) => Status::Failed,
+            (false, false) => Status::Pending,
+        };
+        self.append(Some(&j))?;
+        self.replay(j);
+        Ok(())
+    }
+}
+
+#[test]
+fn queue_roundtrip() {
+    let path = std::env::temp_dir().join("pagepal_queue_roundtrip.json");
+    let mut q = Queue::open(&path).unwrap();
+    let book = Label("Test".to_string());
+    let job =
+        Job::new(book.clone(), 0, 0, "https://example.com/1".parse().unwrap());
+    let other =
+        Job::new(book.clone(), 0, 1, "https://example.com/2".parse().unwrap());
+    q.extend(vec![job.clone(), job.clone(), other.clone()])
+        .unwrap();
+    assert_eq!(q.pending(&book).len(), 2);
+    q.store().unwrap();
+    q.finish(&job, false).unwrap();
+    // Reopened from the queue file and the log
+    let mut q = Queue::open(&path).unwrap();
+    assert_eq!(q.pending(&book)[0].attempts, 1);
+    q.finish(&job, true).unwrap();
+    assert_eq!(q.jobs, vec![other.clone()]);
+    let mut q = Queue::open(&path).unwrap();
+    assert_eq!(q.jobs, vec![other]);
+    q.store().unwrap();
+    assert!(!q.log_path().exists());
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,404 @@
+use crate::{Doc, Error, Finder, Heuristics, Label, Meta, Num};
+use select::{document::Document, node::Node, predicate::Predicate};
+use serde::{Deserialize as des, Serialize as ser};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use url::{Host, Url};
+
+type Cnt = Option<Vec<String>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+/// `tag.class#id[attr=value]`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Compound {
+    tag:     Option<String>,
+    id:      Option<String>,
+    classes: Vec<String>,
+    attrs:   Vec<(String, Option<String>)>,
+}
+impl Compound {
+    fn parse(s: &str) -> Result<Self, Error> {
+        let err = || Error::Parse(format!("selector {:?}", s));
+        let mut c = Compound::default();
+        let mut rest = s;
+        let end = rest.find(|a| ".#[".contains(a)).unwrap_or(rest.len());
+        match &rest[..end] {
+            "" | "*" => {}
+            tag => c.tag = Some(tag.to_ascii_lowercase()),
+        }
+        rest = &rest[end..];
+        while let Some(first) = rest.chars().next() {
+            if first == '[' {
+                let close = rest.find(']').ok_or_else(err)?;
+                let inner = &rest[1..close];
+                c.attrs.push(match inner.split_once('=') {
+                    Some((k, v)) => (
+                        k.trim().to_string(),
+                        Some(
+                            v.trim()
+                                .trim_matches(|a| a == '"' || a == '\'')
+                                .into(),
+                        ),
+                    ),
+                    None => (inner.trim().to_string(), None),
+                });
+                rest = &rest[close + 1..];
+                continue;
+            }
+            let end = rest[1..]
+                .find(|a| ".#[".contains(a))
+                .map_or(rest.len(), |a| a + 1);
+            let name = rest[1..end].to_string();
+            if name.is_empty() {
+                return Err(err());
+            }
+            match first {
+                '.' => c.classes.push(name),
+                '#' => c.id = Some(name),
+                _ => return Err(err()),
+            }
+            rest = &rest[end..];
+        }
+        Ok(c)
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        self.tag.as_deref().map_or(true, |t| node.name() == Some(t)) &&
+            self.id
+                .as_deref()
+                .map_or(true, |i| node.attr("id") == Some(i)) &&
+            self.classes.iter().all(|c| {
+                node.attr("class")
+                    .map_or(false, |a| a.split_whitespace().any(|a| a == c))
+            }) &&
+            self.attrs.iter().all(|(k, v)| match (node.attr(k), v) {
+                (Some(_), None) => true,
+                (Some(a), Some(v)) => a == v,
+                (None, _) => false,
+            })
+    }
+}
+
+/// A small subset of CSS selectors: compounds of tag, `.class`, `#id` and
+/// `[attr]`/`[attr=value]`, joined by descendant or `>` child combinators,
+/// with `,` separated alternatives
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Css(Vec<Vec<(Combinator, Compound)>>);
+impl Css {
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let alts = s
+            .split(',')
+            .map(|alt| {
+                let mut parts = vec![];
+                let mut comb = Combinator::Descendant;
+                for token in alt.replace('>', " > ").split_whitespace() {
+                    match token {
+                        ">" => comb = Combinator::Child,
+                        t => {
+                            parts.push((comb, Compound::parse(t)?));
+                            comb = Combinator::Descendant;
+                        }
+                    }
+                }
+                match parts.is_empty() {
+                    true => Err(Error::Parse(format!("selector {:?}", s))),
+                    false => Ok(parts),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Css(alts))
+    }
+
+    fn matches_from(parts: &[(Combinator, Compound)], node: Node) -> bool {
+        let ((comb, last), rest) = match parts.split_last() {
+            Some(a) => a,
+            None => return true,
+        };
+        if !last.matches(&node) {
+            return false;
+        }
+        if rest.is_empty() {
+            return true;
+        }
+        match comb {
+            Combinator::Child => {
+                node.parent().map_or(false, |p| Self::matches_from(rest, p))
+            }
+            Combinator::Descendant => {
+                let mut p = node.parent();
+                while let Some(n) = p {
+                    if Self::matches_from(rest, n) {
+                        return true;
+                    }
+                    p = n.parent();
+                }
+                false
+            }
+        }
+    }
+}
+impl Predicate for Css {
+    fn matches(&self, node: &Node) -> bool {
+        self.0.iter().any(|a| Self::matches_from(a, *node))
+    }
+}
+
+/// A selector, optionally followed by `@attr` to extract an attribute
+/// instead of the text, e.g. `div.reader img@src`
+#[derive(Clone, Debug, Default, PartialEq, Eq, ser, des)]
+#[serde(transparent)]
+pub struct Rule(pub String);
+impl Rule {
+    fn split(&self) -> Result<(Css, Option<&str>), Error> {
+        match self.0.rsplit_once('@') {
+            Some((css, attr)) => Ok((Css::parse(css)?, Some(attr.trim()))),
+            None => Ok((Css::parse(&self.0)?, None)),
+        }
+    }
+
+    /// Everything the rule matches in the document
+    pub fn all(&self, doc: &Document) -> Vec<String> {
+        match self.split() {
+            Ok((css, attr)) => doc
+                .select(css)
+                .filter_map(|a| match attr {
+                    Some(attr) => a.attr(attr).map(|a| a.to_string()),
+                    None => Some(a.text().trim().to_string()),
+                })
+                .filter(|a| !a.is_empty())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn first(&self, doc: &Document) -> Option<String> {
+        self.all(doc).into_iter().next()
+    }
+}
+
+/// A site described by selectors instead of code, loaded from a TOML or JSON
+/// file. Missing rules fall back to the heuristics.
+#[derive(Clone, Debug, Default, PartialEq, Eq, ser, des)]
+#[serde(default)]
+pub struct SiteDef {
+    pub hosts:            Vec<String>,
+    pub title:            Option<Rule>,
+    pub chapters:         Option<Rule>,
+    /// Whether the chapters are listed newest first
+    pub reverse_chapters: bool,
+    pub images:           Option<Rule>,
+    pub text:             Option<Rule>,
+    pub next:             Option<Rule>,
+    pub cover:            Option<Rule>,
+    pub description:      Option<Rule>,
+    pub alt_titles:       Option<Rule>,
+    pub authors:          Option<Rule>,
+    pub artists:          Option<Rule>,
+    pub genres:           Option<Rule>,
+    pub status:           Option<Rule>,
+    pub rating:           Option<Rule>,
+    /// Url pattern with `{}` where the chapter number is, e.g. `chapter-{}`
+    pub chapter_number:   Option<String>,
+}
+impl SiteDef {
+    /// Reads a definition from a `.toml` or `.json` file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let s = fs::read_to_string(path)?;
+        let def: Self = match path.extension().and_then(|a| a.to_str()) {
+            Some("json") => serde_json::from_str(&s)?,
+            _ => toml::from_str(&s).map_err(|e| Error::Parse(e.to_string()))?,
+        };
+        def.check()?;
+        for host in def.hosts.iter() {
+            Host::parse(host)?;
+        }
+        Ok(def)
+    }
+
+    /// Reads every definition in `dir`, skipping other files. Definitions
+    /// that don't load are skipped as well and returned with the reason, so
+    /// one broken file doesn't take the others down. A missing `dir` has no
+    /// definitions.
+    pub fn load_dir(
+        dir: &Path,
+    ) -> Result<(Vec<Self>, Vec<(PathBuf, Error)>), Error> {
+        let (mut defs, mut failed) = (vec![], vec![]);
+        if !dir.is_dir() {
+            return Ok((defs, failed));
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|a| a.to_str()) {
+                Some("toml") | Some("json") => match Self::load(&path) {
+                    Ok(def) => defs.push(def),
+                    Err(e) => failed.push((path, e)),
+                },
+                _ => {}
+            }
+        }
+        Ok((defs, failed))
+    }
+
+    /// Makes sure all selectors parse
+    pub fn check(&self) -> Result<(), Error> {
+        [
+            &self.title,
+            &self.chapters,
+            &self.images,
+            &self.text,
+            &self.next,
+            &self.cover,
+            &self.description,
+            &self.alt_titles,
+            &self.authors,
+            &self.artists,
+            &self.genres,
+            &self.status,
+            &self.rating,
+        ]
+        .iter()
+        .filter_map(|a| a.as_ref())
+        .try_for_each(|a| a.split().map(|_| ()))
+    }
+}
+
+/// The number following the `{}` placeholder of `pattern` in the url path
+pub fn chapter_number(pattern: &str, url: &Url) -> Option<Num> {
+    let (pre, _) = pattern.split_once("{}")?;
+    let path = url.path();
+    let start = path.find(pre)? + pre.len();
+    let num = path[start..]
+        .chars()
+        .take_while(|a| a.is_ascii_digit() || *a == '.')
+        .collect::<String>();
+    num.trim_end_matches('.').parse::<f32>().ok().map(Num::from)
+}
+
+impl Finder for SiteDef {
+    fn text_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+        match self.text.to_owned() {
+            Some(r) => Box::new(move |doc: Doc| doc.as_ref().map(|a| r.all(a))),
+            None => Heuristics.text_def(),
+        }
+    }
+
+    fn images_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+        match self.images.to_owned() {
+            Some(r) => Box::new(move |doc: Doc| doc.as_ref().map(|a| r.all(a))),
+            None => Heuristics.images_def(),
+        }
+    }
+
+    fn chaps_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+        let reverse = self.reverse_chapters;
+        match self.chapters.to_owned() {
+            Some(r) => Box::new(move |doc: Doc| {
+                doc.as_ref().map(|a| {
+                    let mut chaps = r.all(a);
+                    if reverse {
+                        chaps.reverse();
+                    }
+                    chaps
+                })
+            }),
+            None => Heuristics.chaps_def(),
+        }
+    }
+
+    fn title_def(&self) -> Box<dyn Fn(Doc) -> Option<Label>> {
+        match self.title.to_owned() {
+            Some(r) => Box::new(move |doc: Doc| {
+                doc.as_ref().and_then(|a| r.first(a)).map(Label)
+            }),
+            None => Heuristics.title_def(),
+        }
+    }
+
+    fn next_def(&self) -> Box<dyn Fn(Doc) -> Option<String>> {
+        match self.next.to_owned() {
+            Some(r) => {
+                Box::new(move |doc: Doc| doc.as_ref().and_then(|a| r.first(a)))
+            }
+            None => Heuristics.next_def(),
+        }
+    }
+
+    fn meta_def(&self) -> Box<dyn Fn(Doc) -> Meta> {
+        let rules = vec![
+            ("cover", self.cover.to_owned()),
+            ("description", self.description.to_owned()),
+            ("alt_titles", self.alt_titles.to_owned()),
+            ("author", self.authors.to_owned()),
+            ("artist", self.artists.to_owned()),
+            ("genres", self.genres.to_owned()),
+            ("status", self.status.to_owned()),
+            ("rating", self.rating.to_owned()),
+        ];
+        let fallback = Heuristics.meta_def();
+        Box::new(move |doc: Doc| {
+            let found = doc.as_ref().map_or_else(Vec::new, |d| {
+                rules
+                    .iter()
+                    .filter_map(|(k, r)| {
+                        Some((k.to_string(), r.as_ref()?.first(d)?))
+                    })
+                    .collect()
+            });
+            let mut meta = fallback(doc);
+            meta.extend(found);
+            meta
+        })
+    }
+
+    fn number_def(&self) -> Box<dyn Fn(&Url) -> Option<Num>> {
+        match self.chapter_number.to_owned() {
+            Some(p) => Box::new(move |url: &Url| chapter_number(&p, url)),
+            None => Heuristics.number_def(),
+        }
+    }
+}
+
+#[test]
+fn css_rules() {
+    let doc = Document::from(
+        r#"<html><body>
+        <div class="reader main"><p>One</p><span><p>Two</p></span>
+        <img src="a.png"><img src="b.png" data-x="y"></div>
+        <div id="list"><a href="/c/1">1</a><a href="/c/2">2</a></div>
+        </body></html>"#,
+    );
+    assert_eq!(Rule("div.reader p".into()).all(&doc), vec!["One", "Two"]);
+    assert_eq!(Rule("div.main > p".into()).all(&doc), vec!["One"]);
+    assert_eq!(Rule(".reader img@src".into()).all(&doc), vec![
+        "a.png", "b.png"
+    ]);
+    assert_eq!(Rule("img[data-x=y]@src".into()).all(&doc), vec!["b.png"]);
+    assert_eq!(Rule("#list a, .reader > p".into()).all(&doc), vec![
+        "One", "1", "2"
+    ]);
+    assert!(Css::parse("div..a").is_err());
+    let url = "https://example.com/manga/chapter-12.5/".parse().unwrap();
+    assert_eq!(chapter_number("chapter-{}", &url), Some(Num::from(12.5)));
+}
+
+#[test]
+fn site_dir() {
+    let dir = std::env::temp_dir().join("pagepal_site_dir");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("good.toml"), "hosts = [\"example.com\"]").unwrap();
+    fs::write(dir.join("bad.toml"), "hosts = [").unwrap();
+    fs::write(dir.join("notes.txt"), "not a definition").unwrap();
+    let (defs, failed) = SiteDef::load_dir(&dir).unwrap();
+    assert_eq!(defs.len(), 1);
+    assert_eq!(defs[0].hosts, vec!["example.com"]);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, dir.join("bad.toml"));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,234 @@
+use crate::{Doc, Finder, Label, Meta, SiteFinder};
+use select::predicate::{Attr, Class, Descendant, Name};
+use std::sync::Arc;
+use url::Host;
+
+type Cnt = Option<Vec<String>>;
+
+/// What a selector found, nothing if it matched nothing so the heuristics
+/// are used instead
+fn found(a: Vec<String>) -> Cnt { Some(a).filter(|a| !a.is_empty()) }
+
+/// The built-in `Finder` for a host, if there is one
+pub fn builtin(host: &Host) -> Option<SiteFinder> {
+    match host.to_string().trim_start_matches("www.") {
+        "manganato.com" | "readmanganato.com" | "chapmanganato.com" => {
+            Some(Arc::new(Manganato))
+        }
+        "royalroad.com" => Some(Arc::new(RoyalRoad)),
+        _ => None,
+    }
+}
+
+/// Text of the first node matching the predicate
+macro_rules! first_text {
+    ($doc:expr, $pred:expr) => {
+        $doc.as_ref()
+            .and_then(|a| a.select($pred).next())
+            .map(|a| a.text().trim().to_string())
+    };
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(
+    feature = "trait_ojb_ser",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Manganato;
+impl Finder for Manganato {
+    fn images_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+        Box::new(|doc: Doc| {
+            doc.as_ref().and_then(|a| {
+                found(
+                    a.select(Descendant(
+                        Class("container-chapter-reader"),
+                        Name("img"),
+                    ))
+                    .filter_map(|a| a.attr("src"))
+                    .map(|a| a.to_string())
+                    .collect(),
+                )
+            })
+        })
+    }
+
+    fn chaps_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+        Box::new(|doc: Doc| {
+            doc.as_ref().and_then(|a| {
+                // Listed newest first
+                let mut chaps = a
+                    .select(Descendant(Class("row-content-chapter"), Name("a")))
+                    .filter_map(|a| a.attr("href"))
+                    .map(|a| a.to_string())
+                    .collect::<Vec<_>>();
+                chaps.reverse();
+                found(chaps)
+            })
+        })
+    }
+
+    fn title_def(&self) -> Box<dyn Fn(Doc) -> Option<Label>> {
+        Box::new(|doc: Doc| {
+            first_text!(doc, Descendant(Class("story-info-right"), Name("h1")))
+                .map(Label)
+        })
+    }
+
+    fn next_def(&self) -> Box<dyn Fn(Doc) -> Option<String>> {
+        Box::new(|doc: Doc| {
+            doc.as_ref()
+                .and_then(|a| {
+                    a.select(Class("navi-change-chapter-btn-next")).next()
+                })
+                .and_then(|a| a.attr("href"))
+                .map(|a| a.to_string())
+        })
+    }
+
+    fn meta_def(&self) -> Box<dyn Fn(Doc) -> Meta> {
+        Box::new(|doc: Doc| {
+            let mut meta = Meta::new();
+            if let Some(d) =
+                first_text!(doc, Attr("id", "panel-story-info-description"))
+            {
+                meta.insert("description".to_string(), d);
+            }
+            if let Some(c) = doc
+                .as_ref()
+                .and_then(|a| {
+                    a.select(Descendant(Class("info-image"), Name("img")))
+                        .next()
+                })
+                .and_then(|a| a.attr("src"))
+            {
+                meta.insert("cover".to_string(), c.to_string());
+            }
+            // Rows like `Author(s) :` | `One - Two`
+            if let Some(d) = doc.as_ref() {
+                for row in d
+                    .select(Descendant(Class("variations-tableInfo"), Name("tr")))
+                {
+                    let cell = |c| {
+                        row.select(Class(c))
+                            .next()
+                            .map(|a| a.text().trim().to_string())
+                    };
+                    let key = match cell("table-label").unwrap_or_default() {
+                        l if l.starts_with("Alternative") => "alt_titles",
+                        l if l.starts_with("Author") => "author",
+                        l if l.starts_with("Status") => "status",
+                        l if l.starts_with("Genres") => "genres",
+                        _ => continue,
+                    };
+                    if let Some(v) = cell("table-value") {
+                        let v = match key {
+                            "alt_titles" => v,
+                            _ => v.replace(" - ", ", "),
+                        };
+                        meta.insert(key.to_string(), v);
+                    }
+                }
+            }
+            if let Some(r) = first_text!(doc, Attr("property", "v:average")) {
+                meta.insert("rating".to_string(), r);
+            }
+            meta
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(
+    feature = "trait_ojb_ser",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct RoyalRoad;
+impl Finder for RoyalRoad {
+    fn text_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+        Box::new(|doc: Doc| {
+            doc.as_ref().and_then(|a| {
+                found(
+                    a.select(Descendant(Class("chapter-content"), Name("p")))
+                        .map(|a| a.text())
+                        .collect(),
+                )
+            })
+        })
+    }
+
+    fn chaps_def(&self) -> Box<dyn Fn(Doc) -> Cnt> {
+        Box::new(|doc: Doc| {
+            doc.as_ref().and_then(|a| {
+                found(
+                    a.select(Descendant(Attr("id", "chapters"), Name("a")))
+                        .filter_map(|a| a.attr("href"))
+                        .map(|a| a.to_string())
+                        .collect(),
+                )
+            })
+        })
+    }
+
+    fn title_def(&self) -> Box<dyn Fn(Doc) -> Option<Label>> {
+        Box::new(|doc: Doc| {
+            first_text!(doc, Descendant(Class("fic-title"), Name("h1")))
+                .or_else(|| {
+                    first_text!(doc, Descendant(Class("fic-header"), Name("h2")))
+                })
+                .map(Label)
+        })
+    }
+
+    fn meta_def(&self) -> Box<dyn Fn(Doc) -> Meta> {
+        Box::new(|doc: Doc| {
+            let mut meta = Meta::new();
+            if let Some(d) = first_text!(doc, Class("description")) {
+                meta.insert("description".to_string(), d);
+            }
+            if let Some(a) =
+                first_text!(doc, Descendant(Class("fic-title"), Name("a")))
+            {
+                meta.insert("author".to_string(), a);
+            }
+            if let Some(c) = doc
+                .as_ref()
+                .and_then(|a| {
+                    a.select(Descendant(
+                        Class("cover-art-container"),
+                        Name("img"),
+                    ))
+                    .next()
+                })
+                .and_then(|a| a.attr("src"))
+            {
+                meta.insert("cover".to_string(), c.to_string());
+            }
+            if let Some(d) = doc.as_ref() {
+                let tags = d
+                    .select(Descendant(Class("tags"), Name("a")))
+                    .map(|a| a.text().trim().to_string())
+                    .collect::<Vec<_>>();
+                meta.insert("genres".to_string(), tags.join(", "));
+                if let Some(s) = d
+                    .select(Class("label"))
+                    .map(|a| a.text().trim().to_lowercase())
+                    .find(|a| {
+                        ["ongoing", "completed", "hiatus", "dropped"]
+                            .contains(&a.as_str())
+                    })
+                {
+                    meta.insert("status".to_string(), s);
+                }
+                if let Some(r) = d
+                    .select(Attr("property", "books:rating:value"))
+                    .next()
+                    .and_then(|a| a.attr("content"))
+                {
+                    meta.insert("rating".to_string(), r.to_string());
+                }
+            }
+            meta.insert("language".to_string(), "en".to_string());
+            meta
+        })
+    }
+}
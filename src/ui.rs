@@ -1 +1,827 @@
+use crate::{
+    library::blobs,
+    reader::Pager,
+    Book,
+    DownloadEvent,
+    DownloadStatus,
+    Error,
+    Fetched,
+    Filter,
+    Found,
+    Job,
+    Label,
+    Library,
+    Manga,
+    Media,
+    Novel,
+    Plan,
+    Progress,
+    Queue,
+    Retriever,
+    Store,
+};
+use chrono::{DateTime, Local, Utc};
+use conrod_core::{
+    color,
+    image::{Id as ImageId, Map as ImageMap},
+    position::{Place, Relative},
+    widget,
+    widget_ids,
+    Colorable,
+    Labelable,
+    Positionable,
+    Sizeable,
+    UiCell,
+    Widget,
+};
+use piston_window::{G2dTexture, G2dTextureContext, Texture, TextureSettings};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    time::Duration,
+};
+use tokio::{
+    runtime::Handle,
+    sync::broadcast::{self, error::TryRecvError as EventError},
+};
+use url::Url;
 
+/// Height of a book in the list
+const ROW: f64 = 96.0;
+/// Room for the cover thumbnail of a book
+const THUMB: [f64; 2] = [64.0, ROW - 16.0];
+/// Height of the bar with the filter, kind and sort controls
+const BAR: f64 = 44.0;
+
+/// Decodes an image and uploads it as a texture, scaled down to fit in `max`
+/// if given, returning the texture and its size
+pub fn texture(
+    ctx: &mut G2dTextureContext, data: &[u8], max: Option<[u32; 2]>,
+) -> Result<(G2dTexture, [f64; 2]), Error> {
+    let mut img = image::load_from_memory(data)
+        .map_err(|e| Error::Parse(format!("image: {}", e)))?;
+    if let Some([w, h]) = max {
+        img = img.thumbnail(w, h);
+    }
+    let img = img.to_rgba8();
+    let size = [img.width() as f64, img.height() as f64];
+    let tex = Texture::from_image(ctx, &img, &TextureSettings::new())
+        .map_err(|e| Error::Parse(format!("texture: {:?}", e)))?;
+    Ok((tex, size))
+}
+
+widget_ids! {
+    pub struct BrowserIds {
+        canvas, filter, kind, sort, count, add, rows[], thumbs[]
+    }
+}
+
+/// Which books are listed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    All,
+    Manga,
+    Novels,
+}
+impl Kind {
+    pub fn next(self) -> Self {
+        match self {
+            Kind::All => Kind::Manga,
+            Kind::Manga => Kind::Novels,
+            Kind::Novels => Kind::All,
+        }
+    }
+}
+
+/// Order of the listed books
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sort {
+    Title,
+    /// Most unread chapters first
+    Unread,
+    /// Most recently read first
+    LastRead,
+}
+impl Sort {
+    pub fn next(self) -> Self {
+        match self {
+            Sort::Title => Sort::Unread,
+            Sort::Unread => Sort::LastRead,
+            Sort::LastRead => Sort::Title,
+        }
+    }
+}
+
+/// A book as listed in the browser
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub label:     Label,
+    pub visual:    bool,
+    pub chapters:  usize,
+    pub unread:    usize,
+    pub last_read: Option<DateTime<Utc>>,
+}
+impl Row {
+    pub fn new<T: Media>(label: &Label, book: &Book<T>) -> Self {
+        Self {
+            label:     label.to_owned(),
+            visual:    T::visual(),
+            chapters:  book.chs.len(),
+            unread:    unread(book),
+            last_read: book.last_read,
+        }
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "{}\n{}  ·  {} chapters  ·  {} unread  ·  {}",
+            self.label.0.trim(),
+            match self.visual {
+                true => "Manga",
+                false => "Novel",
+            },
+            self.chapters,
+            self.unread,
+            self.last_read.map_or("never read".to_string(), |a| format!(
+                "read {}",
+                a.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            ))
+        )
+    }
+}
+
+/// Chapters after the one being read, every chapter if the book was never
+/// opened
+pub fn unread<T: Media>(book: &Book<T>) -> usize {
+    if book.last_read.is_none() {
+        return book.chs.len();
+    }
+    match Pager::new(book).current().and_then(|a| a.ch) {
+        Some(ch) => book.chs.range(ch + 1..).count(),
+        None => book.chs.len(),
+    }
+}
+
+/// `bytes` in the largest unit that keeps it above 1
+fn size(bytes: u64) -> String {
+    match bytes {
+        a if a < 1 << 10 => format!("{} B", a),
+        a if a < 1 << 20 => format!("{:.1} KiB", a as f64 / (1 << 10) as f64),
+        a => format!("{:.1} MiB", a as f64 / (1 << 20) as f64),
+    }
+}
+
+/// `d` rounded to minutes, or seconds if it's shorter than one
+fn duration(d: Duration) -> String {
+    match d.as_secs() {
+        a if a < 60 => format!("{}s", a),
+        a => format!("{}m", (a + 30) / 60),
+    }
+}
+
+/// What was picked in the browser
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Choice {
+    Read(Label),
+    /// Adding a book by url
+    Add,
+}
+
+/// Lists the books of the library and picks one to read
+pub struct Browser {
+    pub filter: String,
+    pub kind:   Kind,
+    pub sort:   Sort,
+    ids:        BrowserIds,
+    /// First row shown
+    top:        usize,
+    /// Cover thumbnails and their sizes, `None` for books without a cover
+    thumbs:     HashMap<Label, Option<(ImageId, [f64; 2])>>,
+}
+impl Browser {
+    pub fn new(ids: BrowserIds) -> Self {
+        Self {
+            filter: String::new(),
+            kind: Kind::All,
+            sort: Sort::Title,
+            ids,
+            top: 0,
+            thumbs: HashMap::new(),
+        }
+    }
+
+    /// Books passing the filter, in order
+    pub fn rows(&self, library: &Library) -> Vec<Row> {
+        let filter = Filter {
+            title: Some(self.filter.trim().to_string()).filter(|a| !a.is_empty()),
+            ..Default::default()
+        };
+        let mut rows = library
+            .filter(&filter)
+            .iter()
+            .filter_map(|l| match (library.manga.get(l), library.novels.get(l)) {
+                (Some(b), _) if self.kind != Kind::Novels => Some(Row::new(l, b)),
+                (_, Some(b)) if self.kind != Kind::Manga => Some(Row::new(l, b)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match self.sort {
+            Sort::Title => {}
+            Sort::Unread => rows.sort_by(|a, b| b.unread.cmp(&a.unread)),
+            Sort::LastRead => rows.sort_by(|a, b| b.last_read.cmp(&a.last_read)),
+        }
+        rows
+    }
+
+    /// Drops the thumbnail of a book so it's loaded again, e.g. once its
+    /// cover was saved
+    pub fn refresh(&mut self, label: &Label, map: &mut ImageMap<G2dTexture>) {
+        if let Some(Some((id, _))) = self.thumbs.remove(label) {
+            map.remove(id);
+        }
+    }
+
+    /// Scrolls the list by `dy` rows, positive is up
+    pub fn scroll(&mut self, dy: f64) -> bool {
+        let top = self.top as f64 - dy.signum();
+        self.top = top.max(0.0) as usize;
+        true
+    }
+
+    /// Loads the cover of a book, or the first page of a manga without one
+    fn thumb<T: Media>(
+        book: &Book<T>, store: &mut Option<Store>, dir: &Option<PathBuf>,
+        ctx: &mut G2dTextureContext,
+    ) -> Result<(G2dTexture, [f64; 2]), Error> {
+        let max = Some([THUMB[0] as u32 * 2, THUMB[1] as u32 * 2]);
+        match fs::read(book.cover_path()) {
+            Ok(data) => texture(ctx, &data, max),
+            Err(_) if T::visual() => {
+                let first = book
+                    .content
+                    .values()
+                    .next()
+                    .ok_or_else(|| Error::Missing("cover".to_string()))?;
+                let store = blobs(store, dir)?;
+                texture(ctx, &first.bytes(&book.dir(), store)?, max)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Sets the widgets of the browser for this frame, returning what was
+    /// clicked
+    pub fn set(
+        &mut self, ui: &mut UiCell, library: &mut Library,
+        ctx: &mut G2dTextureContext, map: &mut ImageMap<G2dTexture>,
+    ) -> Option<Choice> {
+        let ids = &self.ids;
+        widget::Canvas::new()
+            .color(color::DARK_CHARCOAL)
+            .set(ids.canvas, ui);
+        let events = widget::TextBox::new(&self.filter)
+            .font_size(14)
+            .w_h(300.0, BAR - 16.0)
+            .top_left_with_margins_on(ids.canvas, 8.0, 8.0)
+            .set(ids.filter, ui);
+        for e in events {
+            if let widget::text_box::Event::Update(s) = e {
+                self.filter = s;
+                self.top = 0;
+            }
+        }
+        if widget::Button::new()
+            .label(&format!("Show: {:?}", self.kind))
+            .label_font_size(14)
+            .w_h(140.0, BAR - 16.0)
+            .right_from(ids.filter, 8.0)
+            .set(ids.kind, ui)
+            .was_clicked()
+        {
+            self.kind = self.kind.next();
+            self.top = 0;
+        }
+        if widget::Button::new()
+            .label(&format!("Sort: {:?}", self.sort))
+            .label_font_size(14)
+            .w_h(140.0, BAR - 16.0)
+            .right_from(ids.kind, 8.0)
+            .set(ids.sort, ui)
+            .was_clicked()
+        {
+            self.sort = self.sort.next();
+        }
+
+        let rows = self.rows(library);
+        widget::Text::new(&match rows.len() {
+            0 if library.manga.is_empty() && library.novels.is_empty() => {
+                "The library is empty".to_string()
+            }
+            n => format!(
+                "{} of {} books",
+                n,
+                library.manga.len() + library.novels.len()
+            ),
+        })
+        .font_size(14)
+        .color(color::LIGHT_GREY)
+        .right_from(ids.sort, 12.0)
+        .set(ids.count, ui);
+        let mut choice = None;
+        if widget::Button::new()
+            .label("Add by url")
+            .label_font_size(14)
+            .w_h(140.0, BAR - 16.0)
+            .top_right_with_margins_on(ids.canvas, 8.0, 8.0)
+            .set(ids.add, ui)
+            .was_clicked()
+        {
+            choice = Some(Choice::Add);
+        }
+
+        let shown = ((ui.win_h - BAR) / ROW).max(1.0) as usize;
+        self.top = self.top.min(rows.len().saturating_sub(shown));
+        let shown = shown.min(rows.len() - self.top);
+        if self.ids.rows.len() < shown {
+            self.ids.rows.resize(shown, &mut ui.widget_id_generator());
+            self.ids.thumbs.resize(shown, &mut ui.widget_id_generator());
+        }
+        for (i, row) in rows.iter().skip(self.top).take(shown).enumerate() {
+            let (row_id, thumb_id) = (self.ids.rows[i], self.ids.thumbs[i]);
+            if widget::Button::new()
+                .label(&row.text())
+                .label_font_size(14)
+                .label_x(Relative::Place(Place::Start(Some(THUMB[0] + 16.0))))
+                .color(color::CHARCOAL)
+                .w_h(ui.win_w - 16.0, ROW - 8.0)
+                .top_left_with_margins_on(
+                    self.ids.canvas,
+                    BAR + i as f64 * ROW,
+                    8.0,
+                )
+                .set(row_id, ui)
+                .was_clicked()
+            {
+                choice = Some(Choice::Read(row.label.to_owned()));
+            }
+            let thumb =
+                self.thumbs.entry(row.label.to_owned()).or_insert_with(|| {
+                    let (store, dir) = (&mut library.blobs, &library.dir);
+                    let tex = match (
+                        library.manga.get(&row.label),
+                        library.novels.get(&row.label),
+                    ) {
+                        (Some(b), _) => Self::thumb(b, store, dir, ctx),
+                        (_, Some(b)) => Self::thumb(b, store, dir, ctx),
+                        _ => Err(Error::Missing(row.label.0.to_owned())),
+                    };
+                    tex.ok().map(|(tex, size)| (map.insert(tex), size))
+                });
+            if let Some((id, size)) = *thumb {
+                let scale = (THUMB[0] / size[0]).min(THUMB[1] / size[1]);
+                widget::Image::new(id)
+                    .w_h(size[0] * scale, size[1] * scale)
+                    .mid_left_with_margin_on(row_id, 8.0)
+                    .graphics_for(row_id)
+                    .set(thumb_id, ui);
+            }
+        }
+        choice
+    }
+}
+
+/// Retrieval spawned on the runtime, its results picked up by the GUI every
+/// frame
+pub struct Downloads {
+    rt:     Handle,
+    tx:     Sender<Fetched<Novel, Manga>>,
+    rx:     Receiver<Fetched<Novel, Manga>>,
+    found:  (Sender<Found<Novel, Manga>>, Receiver<Found<Novel, Manga>>),
+    /// Books whose cover was saved, or why it couldn't be
+    covers: (Sender<Result<Label, Error>>, Receiver<Result<Label, Error>>),
+}
+impl Downloads {
+    /// Spawns retrieval on the runtime of `rt`, the GUI's own thread only
+    /// picks up the results
+    pub fn new(rt: Handle) -> Self {
+        let (tx, rx) = channel();
+        Self {
+            rt,
+            tx,
+            rx,
+            found: channel(),
+            covers: channel(),
+        }
+    }
+
+    /// Downloads the covers the library is missing on another task, see
+    /// `Downloads::covers`
+    pub fn fetch_covers(&self, library: &Library) {
+        let (r, tx) = (library.retriever(), self.covers.0.clone());
+        let wanted = library.missing_covers();
+        self.rt.spawn(async move {
+            Library::<Novel, Manga>::fetch_covers(&r, wanted, |res| {
+                tx.send(res).ok();
+            })
+            .await
+        });
+    }
+
+    /// The books whose cover was saved since the last call, or why it
+    /// couldn't be
+    pub fn covers(&self) -> Vec<Result<Label, Error>> {
+        self.covers.1.try_iter().collect()
+    }
+
+    /// Looks up the book behind `url` on another task, see `Library::plan`
+    pub fn plan(
+        &self, library: &Library, url: String,
+    ) -> Receiver<Result<Plan<Novel, Manga>, Error>> {
+        let (tx, rx) = channel();
+        let r = library.retriever();
+        self.rt.spawn(async move {
+            tx.send(Library::<Novel, Manga>::plan(&r, url).await).ok();
+        });
+        rx
+    }
+
+    /// Downloads the content of `jobs`, all of the same book, on another
+    /// task
+    pub fn start(&self, library: &Library, jobs: Vec<Job>) {
+        let visual = jobs
+            .first()
+            .map_or(false, |a| library.manga.contains_key(&a.book));
+        let (r, tx) = (library.retriever(), self.tx.clone());
+        self.rt.spawn(fetch(r, tx, jobs, visual));
+    }
+
+    /// Finds the chapters of a book added with `Library::add_plan` and
+    /// downloads their content on another task
+    pub fn add(&self, library: &Library, title: Label, links: Vec<Url>) {
+        let visual = library.manga.contains_key(&title);
+        let (r, tx) = (library.retriever(), self.tx.clone());
+        let found = self.found.0.clone();
+        self.rt.spawn(async move {
+            let f = Library::<Novel, Manga>::find(&r, title, links, visual).await;
+            let jobs = f.jobs().to_vec();
+            // Sent before any of the downloads, so they're queued first
+            found.send(f).ok();
+            fetch(r, tx, jobs, visual).await
+        });
+    }
+
+    /// Files the chapters found and the content that finished downloading
+    /// since the last call, returning the jobs and whether they succeeded
+    pub fn poll(
+        &self, library: &mut Library, queue: &mut Queue,
+    ) -> Result<Vec<(Job, bool)>, Error> {
+        for f in self.found.1.try_iter() {
+            library.add_chapters(f, queue)?;
+        }
+        library.receive(self.rx.try_iter().collect(), queue)
+    }
+}
+
+/// Downloads `jobs` and sends the content back through `tx`
+async fn fetch(
+    r: Retriever, tx: Sender<Fetched<Novel, Manga>>, jobs: Vec<Job>, visual: bool,
+) {
+    match visual {
+        true => {
+            r.fetch::<Manga>(jobs, |job, res| {
+                tx.send(Fetched::Manga(job, res)).ok();
+            })
+            .await
+        }
+        false => {
+            r.fetch::<Novel>(jobs, |job, res| {
+                tx.send(Fetched::Novel(job, res)).ok();
+            })
+            .await
+        }
+    }
+}
+
+/// Counts the pages of every chapter of `book` again after missed events:
+/// the finished ones were counted by `Adder::progress` and the rest are
+/// still in `queue`. The bytes downloaded meanwhile are lost.
+fn resync(
+    book: &Label, chs: &mut BTreeMap<u16, [usize; 3]>, progress: &mut Progress,
+    queue: &Queue,
+) {
+    chs.values_mut().for_each(|a| a[2] = a[0]);
+    for j in queue.jobs.iter().filter(|j| &j.book == book) {
+        chs.entry(j.chapter).or_default()[2] += 1;
+    }
+    let [done, failed, total] = chs.values().fold([0; 3], |acc, a| {
+        [acc[0] + a[0], acc[1] + a[1], acc[2] + a[2]]
+    });
+    progress.done = done;
+    progress.failed = failed;
+    progress.total = total;
+}
+
+widget_ids! {
+    pub struct AdderIds {
+        canvas, heading, url, check, info, confirm, close, rows[]
+    }
+}
+
+/// How far adding a book by url got
+pub enum Stage {
+    Input,
+    Checking(Receiver<Result<Plan<Novel, Manga>, Error>>),
+    Confirm(Plan<Novel, Manga>),
+    /// Downloading, with the done, failed and total pages of every chapter
+    /// and the progress the retriever reported
+    Downloading(Label, BTreeMap<u16, [usize; 3]>, Progress),
+    Failed(String),
+}
+
+/// Adds a book by url: shows what's behind the url, adds the book once
+/// confirmed and follows its download
+pub struct Adder {
+    pub url:   String,
+    pub stage: Stage,
+    ids:       AdderIds,
+    events:    Option<broadcast::Receiver<DownloadEvent>>,
+}
+impl Adder {
+    pub fn new(ids: AdderIds) -> Self {
+        Self {
+            url: String::new(),
+            stage: Stage::Input,
+            ids,
+            events: None,
+        }
+    }
+
+    /// Counts a finished download towards the book being added
+    pub fn progress(&mut self, job: &Job, ok: bool) {
+        if let Stage::Downloading(book, chs, _) = &mut self.stage {
+            if &job.book == book {
+                chs.entry(job.chapter).or_default()[!ok as usize] += 1;
+            }
+        }
+    }
+
+    /// Counts what the retriever reported about the book being added, the
+    /// pages of its chapters as they're found. If events were missed the
+    /// pages are counted again from `queue`.
+    fn follow(&mut self, queue: &Queue) {
+        let (events, stage) = (&mut self.events, &mut self.stage);
+        let (rx, book, chs, progress) = match (events, stage) {
+            (Some(rx), Stage::Downloading(book, chs, p)) => (rx, book, chs, p),
+            _ => return,
+        };
+        loop {
+            match rx.try_recv() {
+                Ok(e) if &e.book == book => {
+                    if let (DownloadStatus::Chapter(n), Some(ch)) =
+                        (&e.status, e.chapter)
+                    {
+                        chs.entry(ch).or_default()[2] = *n;
+                    }
+                    progress.add(&e)
+                }
+                Ok(_) => {}
+                Err(EventError::Lagged(_)) => resync(book, chs, progress, queue),
+                Err(EventError::Empty) | Err(EventError::Closed) => break,
+            }
+        }
+    }
+
+    fn check(&mut self, library: &Library, downloads: &Downloads) {
+        match (&self.stage, self.url.trim()) {
+            (Stage::Checking(_), _) | (Stage::Downloading(..), _) | (_, "") => {}
+            (_, url) => {
+                self.stage =
+                    Stage::Checking(downloads.plan(library, url.to_string()))
+            }
+        }
+    }
+
+    fn confirm(&mut self, library: &mut Library, downloads: &Downloads) {
+        let plan = match std::mem::replace(&mut self.stage, Stage::Input) {
+            Stage::Confirm(plan) => plan,
+            stage => {
+                self.stage = stage;
+                return;
+            }
+        };
+        let (title, links) = match library.add_plan(plan) {
+            Ok(added) => added,
+            Err(e) => {
+                self.stage = Stage::Failed(e.to_string());
+                return;
+            }
+        };
+        self.events = Some(library.retriever().subscribe());
+        downloads.add(library, title.clone(), links);
+        self.stage = Stage::Downloading(title, BTreeMap::new(), Progress::new());
+    }
+
+    fn info(&self) -> String {
+        match &self.stage {
+            Stage::Input => {
+                "Paste the url of a book or one of its chapters".into()
+            }
+            Stage::Checking(_) => format!("Looking at {} ...", self.url.trim()),
+            Stage::Confirm(plan) => format!(
+                "{}: {}\n{} chapters",
+                match plan.visual() {
+                    true => "Manga",
+                    false => "Novel",
+                },
+                plan.title().0.trim(),
+                plan.chapters()
+            ),
+            Stage::Downloading(title, chs, _) if chs.is_empty() => {
+                format!("Looking through the chapters of {} ...", title.0.trim())
+            }
+            Stage::Downloading(title, chs, progress) => {
+                let [done, failed, total] =
+                    chs.values().fold([0; 3], |acc, a| {
+                        [acc[0] + a[0], acc[1] + a[1], acc[2] + a[2]]
+                    });
+                format!(
+                    "Downloading {}: {} of {} pages, {} failed\n{}{}",
+                    title.0.trim(),
+                    done,
+                    total,
+                    failed,
+                    size(progress.bytes),
+                    match progress.eta() {
+                        Some(eta) if done + failed < total => {
+                            format!(", about {} left", duration(eta))
+                        }
+                        _ => String::new(),
+                    }
+                )
+            }
+            Stage::Failed(e) => format!("That didn't work: {}", e),
+        }
+    }
+
+    /// Sets the widgets of the dialog for this frame, returning whether it
+    /// was closed. Downloads go on after it's closed.
+    pub fn set(
+        &mut self, ui: &mut UiCell, library: &mut Library, downloads: &Downloads,
+        queue: &Queue,
+    ) -> bool {
+        if let Stage::Checking(rx) = &self.stage {
+            match rx.try_recv() {
+                Ok(Ok(plan)) => self.stage = Stage::Confirm(plan),
+                Ok(Err(e)) => self.stage = Stage::Failed(e.to_string()),
+                Err(TryRecvError::Disconnected) => {
+                    self.stage = Stage::Failed("the check stopped".into())
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        self.follow(queue);
+        let ids = &self.ids;
+        widget::Canvas::new()
+            .color(color::DARK_CHARCOAL)
+            .set(ids.canvas, ui);
+        widget::Text::new("Add a book by url")
+            .font_size(18)
+            .color(color::WHITE)
+            .top_left_with_margins_on(ids.canvas, 16.0, 16.0)
+            .set(ids.heading, ui);
+        let events = widget::TextBox::new(&self.url)
+            .font_size(14)
+            .w_h(600.0, BAR - 16.0)
+            .top_left_with_margins_on(ids.canvas, 52.0, 16.0)
+            .set(ids.url, ui);
+        let mut check = false;
+        for e in events {
+            match e {
+                widget::text_box::Event::Update(s) => self.url = s,
+                widget::text_box::Event::Enter => check = true,
+            }
+        }
+        check |= widget::Button::new()
+            .label("Check")
+            .label_font_size(14)
+            .w_h(100.0, BAR - 16.0)
+            .right_from(ids.url, 8.0)
+            .set(ids.check, ui)
+            .was_clicked();
+        let closed = widget::Button::new()
+            .label(match self.stage {
+                Stage::Downloading(..) => "Close",
+                _ => "Cancel",
+            })
+            .label_font_size(14)
+            .w_h(100.0, BAR - 16.0)
+            .top_right_with_margins_on(ids.canvas, 16.0, 16.0)
+            .set(ids.close, ui)
+            .was_clicked();
+        widget::Text::new(&self.info())
+            .font_size(14)
+            .color(color::LIGHT_GREY)
+            .top_left_with_margins_on(ids.canvas, 96.0, 16.0)
+            .set(ids.info, ui);
+        if let Stage::Confirm(_) = self.stage {
+            if widget::Button::new()
+                .label("Add and download")
+                .label_font_size(14)
+                .w_h(180.0, BAR - 16.0)
+                .top_left_with_margins_on(ids.canvas, 150.0, 16.0)
+                .set(ids.confirm, ui)
+                .was_clicked()
+            {
+                self.confirm(library, downloads);
+            }
+        }
+        if check {
+            self.check(library, downloads);
+        }
+
+        // Chapters still downloading
+        if let Stage::Downloading(_, chs, _) = &self.stage {
+            let going = chs
+                .iter()
+                .filter(|(_, a)| a[0] + a[1] < a[2])
+                .collect::<Vec<_>>();
+            let shown =
+                (((ui.win_h - 150.0) / 22.0).max(0.0) as usize).min(going.len());
+            if self.ids.rows.len() < shown {
+                self.ids.rows.resize(shown, &mut ui.widget_id_generator());
+            }
+            for (i, (ch, [done, failed, total])) in
+                going.into_iter().take(shown).enumerate()
+            {
+                widget::Text::new(&format!(
+                    "Chapter {}: {} of {} pages{}",
+                    ch + 1,
+                    done,
+                    total,
+                    match *failed {
+                        0 => String::new(),
+                        n => format!(", {} failed", n),
+                    }
+                ))
+                .font_size(14)
+                .color(color::WHITE)
+                .top_left_with_margins_on(
+                    self.ids.canvas,
+                    150.0 + i as f64 * 22.0,
+                    16.0,
+                )
+                .set(self.ids.rows[i], ui);
+            }
+        }
+        closed
+    }
+}
+
+#[test]
+fn browser_rows() {
+    use crate::{Chapter, Content};
+    let mut lib: Library = Library::default();
+    for (title, chs, read) in &[("B novel", 3, Some(2)), ("A novel", 2, None)] {
+        let mut book: Book<Novel> = Book {
+            title: Label(title.to_string()),
+            ..Default::default()
+        };
+        for id in 0..*chs {
+            book.chs.insert(id, Chapter {
+                id,
+                ..Default::default()
+            });
+            let mut c = Content::default();
+            c.ch = Some(id);
+            book.content.insert(crate::Num(id, None), c);
+        }
+        if let Some(pos) = read {
+            book.pos = *pos;
+            book.last_read = Some(Utc::now());
+        }
+        lib.novels.insert(book.title.clone(), book);
+    }
+    let manga: Book<Manga> = Book {
+        title: Label("C manga".to_string()),
+        ..Default::default()
+    };
+    lib.manga.insert(manga.title.clone(), manga);
+
+    let mut ui = conrod_core::UiBuilder::new([800.0, 600.0]).build();
+    let mut browser = Browser::new(BrowserIds::new(ui.widget_id_generator()));
+    let titles =
+        |rows: Vec<Row>| rows.into_iter().map(|a| a.label.0).collect::<Vec<_>>();
+    assert_eq!(titles(browser.rows(&lib)), vec![
+        "A novel", "B novel", "C manga"
+    ]);
+    browser.sort = Sort::Unread;
+    let rows = browser.rows(&lib);
+    assert_eq!((rows[0].unread, rows[1].unread), (2, 0));
+    browser.sort = Sort::LastRead;
+    browser.kind = Kind::Novels;
+    assert_eq!(titles(browser.rows(&lib)), vec!["B novel", "A novel"]);
+    browser.filter = "b nov".to_string();
+    assert_eq!(titles(browser.rows(&lib)), vec!["B novel"]);
+}